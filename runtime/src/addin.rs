@@ -0,0 +1,14 @@
+/// Add-in wide metadata set with `xl_addin!`. The name is what Excel shows in the Add-ins dialog
+#[derive(Debug)]
+pub struct AddIn {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub author: &'static str,
+}
+
+inventory::collect!(AddIn);
+
+/// The metadata of this add-in if `xl_addin!` was used
+pub fn addin() -> Option<&'static AddIn> {
+    inventory::iter::<AddIn>.into_iter().next()
+}
@@ -0,0 +1,296 @@
+//! A reference manual of every function in the add-in, built from the registry so it can be
+//! generated on any platform without Excel. Add a binary to the add-in crate
+//!
+//! ```ignore
+//! // src/bin/catalogue.rs
+//! use my_addin as _;
+//!
+//! fn main() {
+//!     xladd_derive_runtime::catalogue::main();
+//! }
+//! ```
+//!
+//! and run `cargo run --bin catalogue -- html docs/functions.html`.
+use crate::{addin, functions, MacroType, XlFunction};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Output formats of the catalogue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Markdown,
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "md" | "markdown" => Ok(Format::Markdown),
+            "html" | "htm" => Ok(Format::Html),
+            _ => Err(format!("Unknown catalogue format {}, expected json, markdown or html", s)),
+        }
+    }
+}
+
+/// Every function in the add-in ordered by category and then name
+pub fn catalogue() -> Vec<&'static XlFunction> {
+    let mut functions = functions().collect::<Vec<_>>();
+    functions.sort_by(|a, b| (a.registration.category, a.excel_name).cmp(&(b.registration.category, b.excel_name)));
+    functions
+}
+
+/// Render the functions in the given format
+pub fn render(functions: &[&XlFunction], format: Format) -> String {
+    match format {
+        Format::Json => to_json(functions),
+        Format::Markdown => to_markdown(functions),
+        Format::Html => to_html(functions),
+    }
+}
+
+/// The functions as a JSON array
+pub fn to_json(functions: &[&XlFunction]) -> String {
+    let functions = functions
+        .iter()
+        .map(|function| {
+            let arguments = function
+                .arguments
+                .iter()
+                .map(|arg| format!("{{\"name\":{},\"type\":{},\"help\":{}}}", json(arg.name), json(arg.rust_type), json(arg.help)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let flags = function.flags.iter().map(|flag| json(flag)).collect::<Vec<_>>().join(",");
+            format!(
+                "{{\"name\":{},\"rust_name\":{},\"category\":{},\"description\":{},\"arguments\":[{}],\"return_type\":{},\"type_text\":{},\"macro_type\":{},\"flags\":[{}]}}",
+                json(function.excel_name),
+                json(function.rust_name),
+                json(function.registration.category),
+                json(function.registration.help_text),
+                arguments,
+                json(function.return_type),
+                json(function.registration.type_text),
+                json(macro_type(function.registration.macro_type)),
+                flags
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[\n{}\n]\n", functions.join(",\n"))
+}
+
+/// The functions as a Markdown document, one section per category
+pub fn to_markdown(functions: &[&XlFunction]) -> String {
+    let mut out = format!("# {}\n", title());
+    let mut category = None;
+    for function in functions {
+        if category != Some(function.registration.category) {
+            category = Some(function.registration.category);
+            let _ = write!(out, "\n## {}\n", or_uncategorised(function.registration.category));
+        }
+        let _ = write!(out, "\n### {}\n\n", function.excel_name);
+        if !function.registration.help_text.is_empty() {
+            let _ = write!(out, "{}\n\n", function.registration.help_text);
+        }
+        let _ = write!(out, "`{}`\n\n", signature(function));
+        if !function.arguments.is_empty() {
+            out.push_str("| Argument | Type | Description |\n|---|---|---|\n");
+            for arg in function.arguments {
+                let _ = writeln!(out, "| {} | `{}` | {} |", cell(arg.name), cell(arg.rust_type), cell(arg.help));
+            }
+            out.push('\n');
+        }
+        if !function.return_type.is_empty() {
+            let _ = write!(out, "Returns `{}`", function.return_type);
+        } else {
+            out.push_str("Command");
+        }
+        if !function.flags.is_empty() || function.registration.macro_type == MacroType::Hidden {
+            let mut flags = function.flags.to_vec();
+            if function.registration.macro_type == MacroType::Hidden {
+                flags.push("hidden");
+            }
+            let _ = write!(out, ", {}", flags.join(", "));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// The functions as a standalone HTML page
+pub fn to_html(functions: &[&XlFunction]) -> String {
+    let title = html(&title());
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n\
+         body {{ font-family: sans-serif; max-width: 60em; margin: auto; }}\n\
+         table {{ border-collapse: collapse; }}\n\
+         th, td {{ border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; }}\n\
+         code {{ background: #f4f4f4; }}\n\
+         </style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        title, title
+    );
+    let mut category = None;
+    for function in functions {
+        if category != Some(function.registration.category) {
+            category = Some(function.registration.category);
+            let _ = writeln!(out, "<h2>{}</h2>", html(or_uncategorised(function.registration.category)));
+        }
+        let name = html(function.excel_name);
+        let _ = writeln!(out, "<h3 id=\"{}\">{}</h3>", name, name);
+        if !function.registration.help_text.is_empty() {
+            let _ = writeln!(out, "<p>{}</p>", html(function.registration.help_text));
+        }
+        let _ = writeln!(out, "<p><code>{}</code></p>", html(&signature(function)));
+        if !function.arguments.is_empty() {
+            out.push_str("<table>\n<tr><th>Argument</th><th>Type</th><th>Description</th></tr>\n");
+            for arg in function.arguments {
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td><code>{}</code></td><td>{}</td></tr>",
+                    html(arg.name),
+                    html(arg.rust_type),
+                    html(arg.help)
+                );
+            }
+            out.push_str("</table>\n");
+        }
+        if !function.return_type.is_empty() {
+            let _ = writeln!(out, "<p>Returns <code>{}</code></p>", html(function.return_type));
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Entry point for a catalogue binary. Takes the format and an optional output file,
+/// `catalogue [json|markdown|html] [file]`, and writes to stdout if no file is given
+pub fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let format = match args.first().map(|format| format.parse::<Format>()).unwrap_or(Ok(Format::Markdown)) {
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: catalogue [json|markdown|html] [file]");
+            std::process::exit(2);
+        }
+    };
+    let text = render(&catalogue(), format);
+    match args.get(1) {
+        Some(file) => {
+            if let Err(e) = std::fs::write(file, text) {
+                eprintln!("Failed to write {}: {}", file, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", text),
+    }
+}
+
+fn title() -> String {
+    match addin() {
+        Some(addin) => format!("{} {}", addin.name, addin.version),
+        None => "Excel functions".to_string(),
+    }
+}
+
+fn or_uncategorised(category: &str) -> &str {
+    if category.is_empty() { "Uncategorised" } else { category }
+}
+
+// How the call looks in a formula e.g. =xl_price(Spot, Strike)
+fn signature(function: &XlFunction) -> String {
+    let args = function.arguments.iter().map(|arg| arg.name).collect::<Vec<_>>().join(", ");
+    format!("={}({})", function.excel_name, args)
+}
+
+fn macro_type(macro_type: MacroType) -> &'static str {
+    match macro_type {
+        MacroType::Hidden => "hidden",
+        MacroType::Function => "function",
+        MacroType::Command => "command",
+    }
+}
+
+fn json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Pipes would end a Markdown table cell early
+fn cell(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+fn html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Registration, XlArgument};
+
+    static PRICE: XlFunction = XlFunction {
+        excel_name: "xl_price",
+        rust_name: "pricing::price",
+        registration: Registration {
+            name: "xl_price",
+            type_text: "QQQ$",
+            arg_text: "Spot,Kind",
+            macro_type: MacroType::Function,
+            category: "Options",
+            shortcut: "",
+            help_topic: "",
+            help_text: "Prices an \"option\"",
+            arg_help: &["Spot price", "call | put"],
+        },
+        arguments: &[
+            XlArgument { name: "Spot", rust_type: "f64", help: "Spot price" },
+            XlArgument { name: "Kind", rust_type: "&str", help: "call | put" },
+        ],
+        return_type: "Vec<f64>",
+        flags: &["thread_safe"],
+        call: None,
+    };
+
+    #[test]
+    fn json_escapes() {
+        let json = to_json(&[&PRICE]);
+        assert!(json.contains(r#""description":"Prices an \"option\"""#));
+        assert!(json.contains(r#"{"name":"Spot","type":"f64","help":"Spot price"}"#));
+        assert!(json.contains(r#""flags":["thread_safe"]"#));
+    }
+
+    #[test]
+    fn markdown_table() {
+        let markdown = to_markdown(&[&PRICE]);
+        assert!(markdown.contains("## Options"));
+        assert!(markdown.contains("`=xl_price(Spot, Kind)`"));
+        assert!(markdown.contains("| Kind | `&str` | call \\| put |"));
+        assert!(markdown.contains("Returns `Vec<f64>`, thread_safe"));
+    }
+
+    #[test]
+    fn html_escapes() {
+        let html = to_html(&[&PRICE]);
+        assert!(html.contains("<code>Vec&lt;f64&gt;</code>"));
+        assert!(html.contains("Prices an &quot;option&quot;"));
+    }
+}
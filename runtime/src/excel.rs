@@ -0,0 +1,15 @@
+use xladd::variant::Variant;
+
+/// Call an Excel function or command. The generated code and the runtime call Excel through
+/// this rather than `xladd::entrypoint::excel12` so that with the `mock` feature the calls go
+/// to the in-process stand in for Excel instead
+pub fn excel12(xlfn: i32, opers: &mut [Variant]) -> Variant {
+    #[cfg(feature = "mock")]
+    {
+        crate::mock::excel12(xlfn, opers)
+    }
+    #[cfg(not(feature = "mock"))]
+    {
+        xladd::entrypoint::excel12(xlfn, opers)
+    }
+}
@@ -0,0 +1,423 @@
+//! Golden file regression tests. A golden file lists calls and the values they are expected to
+//! return, one per row
+//!
+//! ```text
+//! function,arg1,arg2,expected
+//! xl_add,1,2,3
+//! xl_add,1.5,2.25,3.75
+//! xl_sum,"{1,2;3,4}",10
+//! # Rows starting with # are comments
+//! xl_concat,"TRUE",text,TRUETEXT
+//! ```
+//!
+//! Each row is run through the registry by calling the `<name>_xl` function of the named Excel
+//! function, with every Excel callback answered by the [mock](crate::mock), so the file can be
+//! checked on any platform without Excel. Add a binary to the add-in crate
+//!
+//! ```ignore
+//! // src/bin/golden.rs
+//! use my_addin as _;
+//!
+//! fn main() {
+//!     xladd_derive_runtime::golden::main();
+//! }
+//! ```
+//!
+//! and run `cargo run --features xladd-derive-runtime/mock --bin golden -- tests/golden.csv`, or
+//! call [`run_file`] from a test.
+//!
+//! Cells are read as a formula would show them: numbers, `TRUE` and `FALSE`, errors such as `#N/A`,
+//! arrays such as `{1,2;3,4}` and text otherwise. A quoted cell is text unless it is an array, which needs
+//! quoting for its commas, and an empty cell is an omitted argument. Trailing empty cells are ignored, so the last cell of a row is the expected value.
+//! Workbooks (`.xlsx`, `.xlsm`, `.xlsb`, `.xls` and `.ods`) are read from their first sheet with the
+//! `xlsx` feature
+use crate::{error_name, functions, XlValue};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// How far a number may be from the expected value and still match, `|actual - expected| <=
+/// absolute + relative * |expected|`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance { absolute: 1e-9, relative: 1e-9 }
+    }
+}
+
+/// One row of a golden file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    /// Line of a CSV file or row of a sheet, counting from 1
+    pub line: usize,
+    /// Excel name of the function
+    pub function: String,
+    pub args: Vec<XlValue>,
+    pub expected: XlValue,
+}
+
+/// A case that didn't return the expected value
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub case: Case,
+    /// What the function returned, or why it couldn't be called
+    pub actual: Result<XlValue, String>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self.case.args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(",");
+        write!(f, "line {}: ={}({}) ", self.case.line, self.case.function, args)?;
+        match &self.actual {
+            Ok(actual) => write!(f, "returned {} but expected {}", actual, self.case.expected),
+            Err(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The outcome of running a golden file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub passed: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl Report {
+    /// True if every case returned its expected value
+    pub fn success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Read the cases from a CSV or, with the `xlsx` feature, a workbook
+pub fn read(path: &Path) -> Result<Vec<Case>, Box<dyn Error>> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "xlsx" | "xlsm" | "xlsb" | "xls" | "ods" => read_workbook(path),
+        _ => Ok(read_csv(&std::fs::read_to_string(path)?)?),
+    }
+}
+
+/// Read the cases from the text of a CSV file
+pub fn read_csv(text: &str) -> Result<Vec<Case>, String> {
+    let rows = csv_records(text)?
+        .into_iter()
+        .map(|(line, fields)| {
+            let cells = fields
+                .into_iter()
+                .map(|(field, quoted)| if quoted && !field.starts_with('{') { XlValue::Text(field) } else { parse_cell(&field) })
+                .collect();
+            (line, cells)
+        })
+        .collect();
+    cases(rows)
+}
+
+#[cfg(feature = "xlsx")]
+fn read_workbook(path: &Path) -> Result<Vec<Case>, Box<dyn Error>> {
+    use calamine::{open_workbook_auto, Data, Reader};
+    let mut workbook = open_workbook_auto(path)?;
+    let sheet = workbook.worksheet_range_at(0).ok_or("The workbook has no sheets")??;
+    let first_row = sheet.start().map(|(row, _)| row as usize).unwrap_or(0);
+    // Leading empty columns are not part of the range, but an empty first argument has to be kept
+    let first_column = sheet.start().map(|(_, column)| column as usize).unwrap_or(0);
+    let rows = sheet
+        .rows()
+        .enumerate()
+        .map(|(i, row)| {
+            let mut cells = vec![XlValue::Missing; first_column];
+            cells.extend(row.iter().map(|cell| match cell {
+                Data::Int(n) => XlValue::Number(*n as f64),
+                Data::Float(n) => XlValue::Number(*n),
+                Data::Bool(b) => XlValue::Bool(*b),
+                Data::DateTime(d) => XlValue::Number(d.as_f64()),
+                Data::Error(e) => parse_cell(&e.to_string()),
+                // Text cells are text unless they spell out an array
+                Data::String(s) if s.starts_with('{') => parse_cell(s),
+                Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => XlValue::Text(s.clone()),
+                Data::Empty => XlValue::Missing,
+            }));
+            (first_row + i + 1, cells)
+        })
+        .collect();
+    Ok(cases(rows)?)
+}
+
+#[cfg(not(feature = "xlsx"))]
+fn read_workbook(path: &Path) -> Result<Vec<Case>, Box<dyn Error>> {
+    Err(format!("Reading {} needs the xlsx feature of xladd-derive-runtime", path.display()).into())
+}
+
+// Turn rows of cells into cases, skipping blank rows, comments and the header
+fn cases(rows: Vec<(usize, Vec<XlValue>)>) -> Result<Vec<Case>, String> {
+    let mut cases = Vec::new();
+    for (line, mut cells) in rows {
+        while matches!(cells.last(), Some(XlValue::Missing)) {
+            cells.pop();
+        }
+        let function = match cells.first() {
+            None => continue,
+            Some(XlValue::Text(function)) if function.starts_with('#') => continue,
+            Some(XlValue::Text(function)) if function.eq_ignore_ascii_case("function") && cases.is_empty() => continue,
+            Some(XlValue::Text(function)) => function.clone(),
+            Some(other) => return Err(format!("line {}: expected a function name but found {}", line, other)),
+        };
+        if cells.len() < 2 {
+            return Err(format!("line {}: {} has no expected value", line, function));
+        }
+        let expected = cells.pop().unwrap_or(XlValue::Missing);
+        cases.push(Case { line, function, args: cells.split_off(1), expected });
+    }
+    Ok(cases)
+}
+
+/// Call every case through the registry and compare what comes back with the expected value. The
+/// [mock](crate::mock) is held while the cases run, so it must not already be held by the caller
+pub fn run(cases: &[Case], tolerance: Tolerance) -> Report {
+    let _excel = crate::mock::start();
+    let mut report = Report::default();
+    for case in cases {
+        let function = functions().find(|function| function.excel_name.eq_ignore_ascii_case(&case.function));
+        let actual = match function {
+            None => Err(format!("{} is not a function in this add-in", case.function)),
+            Some(function) => match function.call {
+                Some(call) => Ok(call(&case.args)),
+                None => Err(format!("{} is async or a command and can't be called from a golden file", case.function)),
+            },
+        };
+        match actual {
+            Ok(ref actual) if matches(&case.expected, actual, tolerance) => report.passed += 1,
+            actual => report.failures.push(Failure { case: case.clone(), actual }),
+        }
+    }
+    report
+}
+
+/// Read and run a golden file
+pub fn run_file(path: impl AsRef<Path>, tolerance: Tolerance) -> Result<Report, Box<dyn Error>> {
+    Ok(run(&read(path.as_ref())?, tolerance))
+}
+
+/// True if the actual value is the expected one, numbers within the tolerance. A single value
+/// matches a 1x1 array, as it does when Excel shows the array in one cell
+pub fn matches(expected: &XlValue, actual: &XlValue, tolerance: Tolerance) -> bool {
+    match (expected, actual) {
+        (XlValue::Number(e), XlValue::Number(a)) => {
+            e == a || (a - e).abs() <= tolerance.absolute + tolerance.relative * e.abs()
+        }
+        (XlValue::Missing | XlValue::Empty, XlValue::Missing | XlValue::Empty) => true,
+        (XlValue::Array(e), XlValue::Array(a)) => {
+            e.len() == a.len()
+                && e.iter().zip(a).all(|(e, a)| e.len() == a.len() && e.iter().zip(a).all(|(e, a)| matches(e, a, tolerance)))
+        }
+        (e, XlValue::Array(a)) if a.len() == 1 && a[0].len() == 1 => matches(e, &a[0][0], tolerance),
+        (e, a) => e == a,
+    }
+}
+
+/// Entry point for a golden file binary, `golden [--abs 1e-9] [--rel 1e-9] file...`. Prints the
+/// failures and exits with 1 if there are any
+pub fn main() {
+    let usage = || -> ! {
+        eprintln!("Usage: golden [--abs tolerance] [--rel tolerance] file...");
+        std::process::exit(2);
+    };
+    let mut tolerance = Tolerance::default();
+    let mut files = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--abs" => tolerance.absolute = args.next().and_then(|v| v.parse().ok()).unwrap_or_else(|| usage()),
+            "--rel" => tolerance.relative = args.next().and_then(|v| v.parse().ok()).unwrap_or_else(|| usage()),
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        usage();
+    }
+    let mut failed = false;
+    for file in files {
+        match run_file(&file, tolerance) {
+            Ok(report) => {
+                for failure in report.failures.iter() {
+                    println!("{} {}", file, failure);
+                }
+                println!("{}: {} passed, {} failed", file, report.passed, report.failures.len());
+                failed |= !report.success();
+            }
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", file, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Read a cell the way a formula would, e.g. `1.5`, `TRUE`, `#N/A`, `{1,2;3,4}` or text. An empty
+/// cell is an omitted argument
+pub fn parse_cell(text: &str) -> XlValue {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return XlValue::Missing;
+    }
+    if trimmed.eq_ignore_ascii_case("TRUE") || trimmed.eq_ignore_ascii_case("FALSE") {
+        return XlValue::Bool(trimmed.eq_ignore_ascii_case("TRUE"));
+    }
+    if let Some(error) = error_code(trimmed) {
+        return XlValue::Error(error);
+    }
+    // Only things that look like numbers, so that text such as "inf" or "NaN" stays text
+    if trimmed.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c))
+        && let Ok(n) = trimmed.parse::<f64>()
+    {
+        return XlValue::Number(n);
+    }
+    if let Some(inner) = trimmed.strip_prefix('{').and_then(|rest| rest.strip_suffix('}')) {
+        let rows = split_quoted(inner, ';')
+            .iter()
+            .map(|row| split_quoted(row, ',').iter().map(|cell| array_cell(cell)).collect())
+            .collect();
+        return XlValue::Array(rows);
+    }
+    XlValue::Text(text.to_string())
+}
+
+// Array constants quote their text, {"a","b"}, and have no omitted cells
+fn array_cell(text: &str) -> XlValue {
+    let trimmed = text.trim();
+    match trimmed.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        Some(quoted) => XlValue::Text(quoted.replace("\"\"", "\"")),
+        None if trimmed.is_empty() => XlValue::Empty,
+        None => parse_cell(trimmed),
+    }
+}
+
+fn error_code(text: &str) -> Option<u32> {
+    use xladd::xlcall::{xlerrDiv0, xlerrNA, xlerrName, xlerrNull, xlerrNum, xlerrRef, xlerrValue};
+    [xlerrNull, xlerrDiv0, xlerrValue, xlerrRef, xlerrName, xlerrNum, xlerrNA]
+        .into_iter()
+        .find(|e| error_name(*e).eq_ignore_ascii_case(text))
+}
+
+// Split on a separator that isn't inside double quotes
+fn split_quoted(text: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(String::new());
+                continue;
+            }
+            _ => (),
+        }
+        parts.last_mut().unwrap().push(c);
+    }
+    parts
+}
+
+// A field of a CSV file, unquoted, and whether it was quoted
+type Field = (String, bool);
+
+// The records of a CSV file with the line each starts on. Quoted fields can hold commas, newlines and ""
+// for a quote
+fn csv_records(text: &str) -> Result<Vec<(usize, Vec<Field>)>, String> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let (mut quoted, mut in_quotes) = (false, false);
+    let (mut line, mut start) = (1, 1);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.trim().is_empty() && !quoted => {
+                field.clear();
+                quoted = true;
+                in_quotes = true;
+            }
+            ',' if !in_quotes => fields.push((std::mem::take(&mut field), std::mem::take(&mut quoted))),
+            '\r' if !in_quotes => (),
+            '\n' if !in_quotes => {
+                fields.push((std::mem::take(&mut field), std::mem::take(&mut quoted)));
+                records.push((start, std::mem::take(&mut fields)));
+                line += 1;
+                start = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Err(format!("line {}: unterminated quoted field", start));
+    }
+    if !field.is_empty() || quoted || !fields.is_empty() {
+        fields.push((field, quoted));
+        records.push((start, fields));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xladd::xlcall::xlerrNA;
+
+    #[test]
+    fn cells() {
+        assert_eq!(parse_cell("1.5"), XlValue::Number(1.5));
+        assert_eq!(parse_cell("true"), XlValue::Bool(true));
+        assert_eq!(parse_cell("#n/a"), XlValue::Error(xlerrNA));
+        assert_eq!(parse_cell(""), XlValue::Missing);
+        assert_eq!(parse_cell("NaN"), XlValue::Text("NaN".into()));
+        assert_eq!(
+            parse_cell(r#"{1,"a,b";TRUE,}"#),
+            XlValue::Array(vec![vec![1.0.into(), "a,b".into()], vec![true.into(), XlValue::Empty]])
+        );
+    }
+
+    #[test]
+    fn csv_cases() {
+        let cases = read_csv("function,a,b,expected\r\n# comment\nxl_add,1,,3,,\n\nxl_concat,\"TRUE\",\"x\ny\",\"say \"\"hi\"\"\"\n").unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0], Case {
+            line: 3,
+            function: "xl_add".into(),
+            args: vec![1.0.into(), XlValue::Missing],
+            expected: 3.0.into(),
+        });
+        assert_eq!(cases[1].line, 5);
+        assert_eq!(cases[1].args, vec!["TRUE".into(), "x\ny".into()]);
+        assert_eq!(cases[1].expected, "say \"hi\"".into());
+        assert_eq!(read_csv("xl_sum,\"{1,2}\",3").unwrap()[0].args, vec![XlValue::Array(vec![vec![1.0.into(), 2.0.into()]])]);
+        assert!(read_csv("xl_add\n").is_err());
+        assert!(read_csv("xl_add,\"1\n").is_err());
+    }
+
+    #[test]
+    fn tolerance() {
+        let tolerance = Tolerance { absolute: 0.01, relative: 0.0 };
+        assert!(matches(&1.0.into(), &1.005.into(), tolerance));
+        assert!(!matches(&1.0.into(), &1.02.into(), tolerance));
+        assert!(matches(&1.0.into(), &XlValue::Array(vec![vec![1.0.into()]]), tolerance));
+        assert!(!matches(&XlValue::Array(vec![vec![1.0.into(), 2.0.into()]]), &XlValue::Array(vec![vec![1.0.into()]]), tolerance));
+        assert!(!matches(&"1".into(), &1.0.into(), tolerance));
+    }
+}
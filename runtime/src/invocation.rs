@@ -0,0 +1,136 @@
+use crate::Panic;
+use std::fmt::{Debug, Display};
+use std::panic::UnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// One call of an Excel function, from the arguments coming in to the result going back. With the
+/// `tracing` feature the call runs in a span named after the Excel function, with a field for each
+/// argument and `outcome`, `result` or `error` and `duration_ms` fields recorded when it returns.
+/// Otherwise the same details are logged at trace level
+pub struct Invocation {
+    name: &'static str,
+    start: Instant,
+    // Only the first outcome is recorded, an error converting the result comes after the call was recorded
+    finished: AtomicBool,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Invocation {
+    #[cfg(feature = "tracing")]
+    pub fn new(name: &'static str, span: tracing::Span) -> Invocation {
+        Invocation { name, start: Instant::now(), finished: AtomicBool::new(false), span }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn new(name: &'static str) -> Invocation {
+        log::trace!("{} called", name);
+        Invocation { name, start: Instant::now(), finished: AtomicBool::new(false) }
+    }
+
+    /// An argument once it has been converted to its Rust type
+    pub fn argument(&self, name: &str, value: &dyn Debug) {
+        #[cfg(feature = "tracing")]
+        self.span.record(name, tracing::field::debug(value));
+        #[cfg(not(feature = "tracing"))]
+        log::trace!("{}:[{:?}]", name, value);
+    }
+
+    /// Run the argument conversions and the call, recording an error converting the arguments. How
+    /// the user function itself ends is recorded by [`catch_panic`](Self::catch_panic) and
+    /// [`result`](Self::result)
+    pub fn run<T, E: Display>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let result = f();
+        if let Err(e) = &result {
+            self.finish("error", || e.to_string());
+        }
+        result
+    }
+
+    /// Call the user function in the span, catching a panic as [`crate::catch_panic`] does
+    pub fn catch_panic<F: FnOnce() -> R + UnwindSafe, R>(&self, f: F) -> Result<R, Panic> {
+        #[cfg(feature = "tracing")]
+        let result = self.span.in_scope(|| crate::catch_panic(self.name, f));
+        #[cfg(not(feature = "tracing"))]
+        let result = crate::catch_panic(self.name, f);
+        if let Err(panic) = &result {
+            self.finish("panic", || panic.message.clone());
+        }
+        result
+    }
+
+    /// Record what the user function returned and pass it on
+    pub fn result<T: Debug, E: Display>(&self, result: Result<T, E>) -> Result<T, E> {
+        match &result {
+            Ok(value) => self.finish("ok", || format!("{:?}", value)),
+            Err(e) => self.finish("error", || e.to_string()),
+        }
+        result
+    }
+
+    // The detail is the result of an ok call or the error message otherwise, only formatted if it
+    // is going to be seen
+    fn finish(&self, outcome: &str, detail: impl FnOnce() -> String) {
+        if self.finished.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let duration = self.start.elapsed();
+        #[cfg(feature = "tracing")]
+        if !self.span.is_disabled() {
+            self.span.record("outcome", outcome);
+            self.span.record(if outcome == "ok" { "result" } else { "error" }, detail());
+            self.span.record("duration_ms", duration.as_secs_f64() * 1000.0);
+        }
+        #[cfg(not(feature = "tracing"))]
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("{} {} in {:?} [{}]", self.name, outcome, duration, detail());
+        }
+    }
+}
+
+/// Start an [`Invocation`] of the named Excel function, declaring a span field for each argument
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! invocation {
+    ($name:literal $(, $arg:ident)*) => {
+        $crate::Invocation::new(
+            $name,
+            $crate::tracing::info_span!(
+                $name,
+                $($arg = $crate::tracing::field::Empty,)*
+                outcome = $crate::tracing::field::Empty,
+                result = $crate::tracing::field::Empty,
+                error = $crate::tracing::field::Empty,
+                duration_ms = $crate::tracing::field::Empty
+            ),
+        )
+    };
+}
+
+/// Start an [`Invocation`] of the named Excel function
+#[cfg(not(feature = "tracing"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! invocation {
+    ($name:literal $(, $arg:ident)*) => {
+        $crate::Invocation::new($name)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_outcome_is_kept() {
+        let invocation = crate::invocation!("xl_test", x);
+        invocation.argument("x", &1.5);
+        assert!(invocation.catch_panic(|| -> f64 { panic!("boom") }).is_err());
+        assert!(invocation.finished.load(Ordering::Relaxed));
+        // Still passed on after the panic was recorded
+        assert_eq!(invocation.result::<_, String>(Ok(1.0)), Ok(1.0));
+        assert_eq!(invocation.run(|| Err::<f64, _>("bad".to_string())), Err("bad".to_string()));
+    }
+}
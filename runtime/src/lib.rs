@@ -0,0 +1,38 @@
+//! Runtime support for the code generated by `xladd-derive`: types that can be used
+//! in the signature of an `#[xl_func]` alongside the basic Rust types, and the
+//! registration call made by the generated `register_<name>` functions. The macro
+//! crate can only export macros, so anything the generated code or a user function
+//! needs to name lives here.
+
+// Used by the registry code generated by the macros
+#[doc(hidden)]
+pub use inventory;
+// Used by the spans generated with the tracing feature
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+pub use tracing;
+
+mod addin;
+pub mod catalogue;
+mod excel;
+#[cfg(feature = "mock")]
+pub mod golden;
+mod invocation;
+#[cfg(feature = "mock")]
+pub mod mock;
+mod panic;
+mod registration;
+mod registry;
+mod xlrange;
+mod xlref;
+mod xlvalue;
+
+pub use addin::{addin, AddIn};
+pub use excel::excel12;
+pub use invocation::Invocation;
+pub use panic::{catch_panic, Panic};
+pub use registration::{register, unregister_all, MacroType, Registration};
+pub use registry::{duplicate_names, functions, register_all, XlArgument, XlFunction};
+pub use xlrange::XlRange;
+pub use xlref::XlRef;
+pub use xlvalue::{error_name, XlValue};
@@ -0,0 +1,271 @@
+//! An in-process stand in for Excel so the generated entry points can be tested without it, for
+//! example in CI on Linux. Enable it for tests only
+//!
+//! ```toml
+//! [dev-dependencies]
+//! xladd-derive-runtime = { version = "0.9", features = ["mock"] }
+//! ```
+//!
+//! With the feature on every callback into Excel made by the generated code and the runtime is
+//! answered here and recorded, so a test can call the exported `xl_<name>` function with
+//! arguments built by [`oper`] and check what came back
+//!
+//! ```ignore
+//! #[test]
+//! fn add() {
+//!     let excel = xladd_derive_runtime::mock::start();
+//!     let result = unsafe { mock::result(xl_add(mock::oper(1.0), mock::oper(2.0))) };
+//!     assert_eq!(f64::try_from(&result).unwrap(), 3.0);
+//!     assert!(xladd_derive_runtime::register_all());
+//!     assert_eq!(excel.registrations()[0].name, "xl_add");
+//! }
+//! ```
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use xladd::variant::Variant;
+use xladd::xlcall::{
+    xlAsyncReturn, xlCoerce, xlGetName, xlSheetNm, xlerrValue, xlfRegister, xlfSetName, xlfUnregister, LPXLOPER12,
+};
+
+// Shared so it can be called without holding the state, a handler may call back into Excel. Async
+// functions call Excel from their own threads so it has to be Sync as well
+type Handler = Arc<dyn Fn(i32, &[Variant]) -> Option<Variant> + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    calls: Vec<Call>,
+    registrations: Vec<Registered>,
+    async_returns: Vec<Variant>,
+    handler: Option<Handler>,
+}
+
+// Variant isn't Send because of the raw pointers in XLOPER12, but the recorded values are copies
+// that own their data, so they can be handed to whichever thread reads them
+unsafe impl Send for State {}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    calls: Vec::new(),
+    registrations: Vec::new(),
+    async_returns: Vec::new(),
+    handler: None,
+});
+
+// Tests run in parallel but there is only one Excel, so a test holds this while it uses the mock
+static EXCEL: Mutex<()> = Mutex::new(());
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A callback into Excel, the function number from `xladd::xlcall` and its arguments
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub function: i32,
+    pub args: Vec<Variant>,
+}
+
+/// A call to `xlfRegister` taken apart into its fields
+#[derive(Clone, Debug, Default)]
+pub struct Registered {
+    /// Register id handed back to the add-in
+    pub id: f64,
+    pub name: String,
+    pub type_text: String,
+    pub arg_text: String,
+    pub macro_type: f64,
+    pub category: String,
+    pub shortcut: String,
+    pub help_topic: String,
+    pub help_text: String,
+    pub arg_help: Vec<String>,
+}
+
+/// Exclusive use of the mock for the rest of a test. Starting clears everything recorded by
+/// earlier tests
+pub struct MockExcel {
+    _excel: MutexGuard<'static, ()>,
+}
+
+/// Take the mock for the duration of a test, waiting for any other test using it to finish
+pub fn start() -> MockExcel {
+    let excel = EXCEL.lock().unwrap_or_else(|e| e.into_inner());
+    *state() = State::default();
+    MockExcel { _excel: excel }
+}
+
+impl MockExcel {
+    /// Every callback into Excel so far, in order
+    pub fn calls(&self) -> Vec<Call> {
+        state().calls.clone()
+    }
+
+    /// Every function and command registered so far
+    pub fn registrations(&self) -> Vec<Registered> {
+        state().registrations.clone()
+    }
+
+    /// Results handed back through `xlAsyncReturn` so far
+    pub fn async_returns(&self) -> Vec<Variant> {
+        state().async_returns.clone()
+    }
+
+    /// Wait until `count` async results have come back, or the timeout expires, and return
+    /// whatever has come back. Async functions return on another thread
+    pub fn wait_for_async(&self, count: usize, timeout: Duration) -> Vec<Variant> {
+        let start = Instant::now();
+        loop {
+            let returns = self.async_returns();
+            if returns.len() >= count || start.elapsed() > timeout {
+                return returns;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Answer callbacks the mock doesn't know about, such as `xlfCaller`, or override the
+    /// built in answers. Return `None` to fall back to the default. The handler can call back
+    /// into Excel, for example to coerce an argument
+    pub fn on_call(&self, handler: impl Fn(i32, &[Variant]) -> Option<Variant> + Send + Sync + 'static) {
+        state().handler = Some(Arc::new(handler));
+    }
+}
+
+/// An argument for an exported function. Excel owns its arguments so they are not freed
+pub fn oper(value: impl Into<Variant>) -> LPXLOPER12 {
+    LPXLOPER12::from(value.into())
+}
+
+/// An omitted argument
+pub fn missing() -> LPXLOPER12 {
+    LPXLOPER12::from(Variant::missing())
+}
+
+/// An argument holding an Excel error such as `xlerrNA`
+pub fn error(xlerr: u32) -> LPXLOPER12 {
+    LPXLOPER12::from(Variant::from_err(xlerr))
+}
+
+/// A values argument with the given number of columns, as if a range had been selected
+pub fn array(values: &[f64], columns: usize) -> LPXLOPER12 {
+    LPXLOPER12::from(Variant::from(&(values, columns)))
+}
+
+/// A strings argument with the given number of columns
+pub fn text_array(values: &[&str], columns: usize) -> LPXLOPER12 {
+    LPXLOPER12::from(Variant::from(&(values, columns)))
+}
+
+/// The value returned by an exported function
+///
+/// # Safety
+/// `result` must be the pointer returned by an exported function
+pub unsafe fn result(result: LPXLOPER12) -> Variant {
+    Variant::from(result)
+}
+
+// Stands in for xladd::entrypoint::excel12 when the mock feature is on
+pub(crate) fn excel12(xlfn: i32, opers: &mut [Variant]) -> Variant {
+    let handler = {
+        let mut state = state();
+        state.calls.push(Call { function: xlfn, args: opers.to_vec() });
+        state.handler.clone()
+    };
+    if let Some(result) = handler.and_then(|handler| handler(xlfn, opers)) {
+        return result;
+    }
+    let mut state = state();
+    let text = |i: usize| opers.get(i).and_then(|v| String::try_from(v).ok()).unwrap_or_default();
+    match xlfn {
+        f if f == xlGetName => Variant::from("mock.xll"),
+        f if f == xlfRegister => {
+            let id = state.registrations.len() as f64 + 1.0;
+            let registered = Registered {
+                id,
+                name: text(1),
+                type_text: text(2),
+                arg_text: text(4),
+                macro_type: opers.get(5).and_then(|v| f64::try_from(v).ok()).unwrap_or_default(),
+                category: text(6),
+                shortcut: text(7),
+                help_topic: text(8),
+                help_text: text(9),
+                arg_help: (10..opers.len()).map(text).collect(),
+            };
+            state.registrations.push(registered);
+            Variant::from(id)
+        }
+        f if f == xlfUnregister || f == xlfSetName => Variant::from(true),
+        f if f == xlAsyncReturn => {
+            state.async_returns.push(opers.get(1).cloned().unwrap_or_default());
+            Variant::from(true)
+        }
+        f if f == xlSheetNm => Variant::from("[Book1]Sheet1"),
+        // Arguments built here are already values, so there is nothing to dereference
+        f if f == xlCoerce => opers.first().cloned().unwrap_or_default(),
+        _ => Variant::from_err(xlerrValue),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{register, MacroType, Registration};
+    use xladd::xlcall::xlfCaller;
+
+    #[test]
+    fn records_registrations() {
+        let excel = start();
+        register(&Registration {
+            name: "xl_price",
+            type_text: "QQ$",
+            arg_text: "Spot",
+            macro_type: MacroType::Function,
+            category: "Options",
+            help_text: "Prices an option",
+            arg_help: &["Spot price"],
+            ..Default::default()
+        });
+        let registered = excel.registrations();
+        assert_eq!(registered.len(), 1);
+        assert_eq!(registered[0].name, "xl_price");
+        assert_eq!(registered[0].arg_help, vec!["Spot price".to_string()]);
+        assert_eq!(excel.calls()[0].function, xlGetName);
+    }
+
+    #[test]
+    fn unregisters_everything() {
+        let excel = start();
+        let function = register(&Registration { name: "xl_price", ..Default::default() }).unwrap();
+        let command = register(&Registration { name: "xl_refresh", macro_type: MacroType::Command, ..Default::default() }).unwrap();
+        crate::unregister_all();
+        let calls = excel.calls();
+        let unregistered = calls.iter().filter(|call| call.function == xlfUnregister).map(|call| f64::try_from(&call.args[0]).unwrap()).collect::<Vec<_>>();
+        assert!(unregistered.contains(&function) && unregistered.contains(&command));
+        // Only functions have a name to delete
+        let names = calls.iter().filter(|call| call.function == xlfSetName).map(|call| String::try_from(&call.args[0]).unwrap()).collect::<Vec<_>>();
+        assert!(names.contains(&"xl_price".to_string()) && !names.contains(&"xl_refresh".to_string()));
+        crate::unregister_all();
+        assert_eq!(excel.calls().len(), calls.len());
+    }
+
+    #[test]
+    fn custom_handler() {
+        let excel = start();
+        excel.on_call(|function, _| if function == xlfCaller { Some(Variant::from("caller")) } else { None });
+        let caller = crate::excel12(xlfCaller, &mut []);
+        assert_eq!(String::try_from(&caller).ok().as_deref(), Some("caller"));
+    }
+
+    #[test]
+    fn nested_calls() {
+        let excel = start();
+        // The handler calls back into Excel, which must not wait for the call it is answering
+        excel.on_call(|function, _| {
+            (function == xlfCaller).then(|| crate::excel12(xlCoerce, &mut [Variant::from("nested")]))
+        });
+        let caller = crate::excel12(xlfCaller, &mut []);
+        assert_eq!(String::try_from(&caller).ok().as_deref(), Some("nested"));
+        let calls = excel.calls().iter().map(|call| call.function).collect::<Vec<_>>();
+        assert_eq!(calls, vec![xlfCaller, xlCoerce]);
+    }
+}
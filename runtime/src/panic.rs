@@ -0,0 +1,120 @@
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::{Cell, RefCell};
+use std::panic::{catch_unwind, PanicHookInfo, UnwindSafe};
+use std::sync::Once;
+
+// Longest message handed back to a cell, the full details go to the log
+const MAX_MESSAGE_LENGTH: usize = 255;
+
+static HOOK: Once = Once::new();
+
+thread_local! {
+    // Set while a function called through catch_panic is running on this thread
+    static GUARDED: Cell<bool> = const { Cell::new(false) };
+    // What the hook saw of the last panic on this thread
+    static LAST_PANIC: RefCell<Option<Panic>> = const { RefCell::new(None) };
+}
+
+/// A panic caught in a user function, as recorded by the hook installed by [`catch_panic`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Panic {
+    /// The message passed to `panic!`
+    pub message: String,
+    /// `file:line:column` of the panic
+    pub location: Option<String>,
+    /// Only captured when `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` is set
+    pub backtrace: Option<String>,
+}
+
+impl Panic {
+    /// The message, location and backtrace, as written to the log
+    pub fn details(&self) -> String {
+        let mut details = self.message.clone();
+        if let Some(location) = &self.location {
+            details = format!("{} at {}", details, location);
+        }
+        if let Some(backtrace) = &self.backtrace {
+            details = format!("{}\n{}", details, backtrace);
+        }
+        details
+    }
+}
+
+/// The message shortened to fit a cell
+impl std::fmt::Display for Panic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = format!("Panic: {}", self.message.lines().next().unwrap_or(""));
+        if message.chars().count() <= MAX_MESSAGE_LENGTH {
+            return write!(f, "{}", message);
+        }
+        let (end, _) = message.char_indices().nth(MAX_MESSAGE_LENGTH - 3).unwrap_or_default();
+        write!(f, "{}...", &message[..end])
+    }
+}
+
+/// Call a user function, catching a panic rather than letting it unwind into Excel. The panic is
+/// logged in full against the Excel function name and returned so the caller can hand a short
+/// message back to the cell. A panic hook is installed the first time this is called, it records
+/// panics in functions called from here and passes any others on to the hook it replaced
+pub fn catch_panic<F: FnOnce() -> R + UnwindSafe, R>(function: &str, f: F) -> Result<R, Panic> {
+    HOOK.call_once(install_hook);
+    let guarded = GUARDED.with(|g| g.replace(true));
+    let result = catch_unwind(f);
+    GUARDED.with(|g| g.set(guarded));
+    result.map_err(|payload| {
+        // The hook may have been replaced since, in which case only the payload is left
+        let panic = LAST_PANIC.with(|last| last.borrow_mut().take()).unwrap_or_else(|| Panic {
+            message: payload_message(payload.as_ref()),
+            ..Default::default()
+        });
+        log::error!("{} panicked: {}", function, panic.details());
+        panic
+    })
+}
+
+fn install_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info: &PanicHookInfo| {
+        if !GUARDED.with(Cell::get) {
+            return previous(info);
+        }
+        let backtrace = Backtrace::capture();
+        let panic = Panic {
+            message: payload_message(info.payload()),
+            location: info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+            backtrace: (backtrace.status() == BacktraceStatus::Captured).then(|| backtrace.to_string()),
+        };
+        LAST_PANIC.with(|last| *last.borrow_mut() = Some(panic));
+    }));
+}
+
+fn payload_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_location() {
+        let panic = catch_panic("xl_test", || -> f64 { panic!("index {} out of range", 3) }).unwrap_err();
+        assert_eq!(panic.message, "index 3 out of range");
+        assert!(panic.location.as_deref().unwrap().starts_with(file!()));
+        assert_eq!(panic.to_string(), "Panic: index 3 out of range");
+        assert_eq!(catch_panic("xl_test", || 1.0), Ok(1.0));
+    }
+
+    #[test]
+    fn long_messages_are_shortened() {
+        let panic = Panic { message: "x".repeat(1000), ..Default::default() };
+        assert_eq!(panic.to_string().chars().count(), MAX_MESSAGE_LENGTH);
+        assert!(panic.details().len() >= 1000);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::excel12;
+use std::sync::Mutex;
+use xladd::variant::Variant;
+use xladd::xlcall::{xlGetName, xlfRegister, xlfSetName, xlfUnregister};
+
+// Names and register ids of everything registered so they can be unregistered when the add-in is unloaded
+static REGISTERED: Mutex<Vec<(String, MacroType, f64)>> = Mutex::new(Vec::new());
+
+/// How Excel exposes a registered procedure
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MacroType {
+    /// A worksheet function that does not appear in the Function Wizard
+    Hidden = 0,
+    /// A worksheet function
+    #[default]
+    Function = 1,
+    /// A command that can be run from a button, the ribbon or `Application.Run`
+    Command = 2,
+}
+
+/// Everything passed to `xlfRegister` for one exported procedure. The `register_<name>`
+/// functions generated by `#[xl_func]` and `#[xl_command]` fill this in.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Registration<'a> {
+    /// Exported symbol, also used as the name in Excel
+    pub name: &'a str,
+    pub type_text: &'a str,
+    /// Comma separated argument names
+    pub arg_text: &'a str,
+    pub macro_type: MacroType,
+    pub category: &'a str,
+    /// Shortcut key for commands, e.g. "R" for Ctrl+Shift+R
+    pub shortcut: &'a str,
+    pub help_topic: &'a str,
+    pub help_text: &'a str,
+    pub arg_help: &'a [&'a str],
+}
+
+/// Register a procedure with Excel, returning the register id if Excel accepted it
+pub fn register(registration: &Registration) -> Option<f64> {
+    let dll_name = excel12(xlGetName, &mut []);
+    let mut opers = vec![
+        dll_name,
+        Variant::from(registration.name),
+        Variant::from(registration.type_text),
+        Variant::from(registration.name),
+        Variant::from(registration.arg_text),
+        Variant::from(registration.macro_type as i32 as f64),
+        Variant::from(registration.category),
+        Variant::from(registration.shortcut),
+        Variant::from(registration.help_topic),
+        Variant::from(registration.help_text),
+    ];
+    opers.extend(registration.arg_help.iter().map(|help| Variant::from(*help)));
+    let id = excel12(xlfRegister, &mut opers);
+    match f64::try_from(&id) {
+        Ok(id) => {
+            if let Ok(mut registered) = REGISTERED.lock() {
+                registered.push((registration.name.to_string(), registration.macro_type, id));
+            }
+            Some(id)
+        }
+        Err(_) => {
+            log::error!("Failed to register {}", registration.name);
+            None
+        }
+    }
+}
+
+/// Unregister everything registered through [`register`]. Called from `xlAutoClose` and
+/// `xlAutoRemove` so Excel doesn't call into the dll after it has been unloaded
+pub fn unregister_all() {
+    let registered = match REGISTERED.lock() {
+        Ok(mut registered) => std::mem::take(&mut *registered),
+        Err(_) => return,
+    };
+    for (name, macro_type, id) in registered {
+        // xlfUnregister leaves worksheet function names behind in the Function Wizard, deleting the name removes them
+        if macro_type != MacroType::Command {
+            excel12(xlfSetName, &mut [Variant::from(name.as_str())]);
+        }
+        excel12(xlfUnregister, &mut [Variant::from(id)]);
+        log::trace!("Unregistered {}", name);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{register, Registration, XlValue};
+use std::collections::BTreeMap;
+
+/// One `#[xl_func]` or `#[xl_command]` in the add-in. The macros submit one of these for
+/// every function so the add-in can be registered and checked as a whole.
+#[derive(Debug)]
+pub struct XlFunction {
+    /// Name the function is exported and registered under
+    pub excel_name: &'static str,
+    /// Path of the Rust function it wraps
+    pub rust_name: &'static str,
+    /// Everything passed to `xlfRegister`, the category, description and type text among others
+    pub registration: Registration<'static>,
+    /// One entry per Rust parameter, a variadic parameter is listed once
+    pub arguments: &'static [XlArgument],
+    /// Rust type returned to Excel, the `Ok` type for functions returning a `Result`. Empty for commands
+    pub return_type: &'static str,
+    /// Modifiers the function was registered with: `async`, `volatile`, `macro_equivalent`,
+    /// `cluster_safe` and `thread_safe`
+    pub flags: &'static [&'static str],
+    /// The generated `<name>_xl` function, calling the function with worksheet values. `None` for
+    /// async functions and commands
+    pub call: Option<fn(&[XlValue]) -> XlValue>,
+}
+
+/// A parameter of an [`XlFunction`]
+#[derive(Debug)]
+pub struct XlArgument {
+    /// Name shown in Excel
+    pub name: &'static str,
+    /// Type as written in the Rust signature
+    pub rust_type: &'static str,
+    pub help: &'static str,
+}
+
+inventory::collect!(XlFunction);
+
+/// Every function in the add-in, in no particular order
+pub fn functions() -> impl Iterator<Item = &'static XlFunction> {
+    inventory::iter::<XlFunction>.into_iter()
+}
+
+/// Excel names exported by more than one function, with the Rust functions that export them.
+/// Excel names are case insensitive so `xl_Add` and `xl_add` clash.
+///
+/// Functions generated by the macros export a marker symbol named after their lower cased Excel name,
+/// so a clash between two of them fails to link before this can see it. This catches functions that
+/// reach the registry some other way, such as an `XlFunction` submitted by hand
+pub fn duplicate_names() -> Vec<(String, Vec<&'static str>)> {
+    duplicates(functions())
+}
+
+fn duplicates<'a>(functions: impl Iterator<Item = &'a XlFunction>) -> Vec<(String, Vec<&'static str>)> {
+    let mut names = BTreeMap::<String, Vec<&'static str>>::new();
+    for function in functions {
+        names.entry(function.excel_name.to_ascii_lowercase()).or_default().push(function.rust_name);
+    }
+    names
+        .into_iter()
+        .filter(|(_, functions)| functions.len() > 1)
+        .map(|(name, mut functions)| {
+            functions.sort();
+            (name, functions)
+        })
+        .collect()
+}
+
+fn clash_message(name: &str, functions: &[&str]) -> String {
+    format!("Excel name {} is exported by more than one function: {}", name, functions.join(", "))
+}
+
+/// Register every function in the add-in. Functions whose Excel name clashes with another are
+/// logged and skipped rather than silently overwriting each other, in which case this returns false
+pub fn register_all() -> bool {
+    register_functions(&functions().collect::<Vec<_>>())
+}
+
+fn register_functions(functions: &[&XlFunction]) -> bool {
+    let duplicates = duplicates(functions.iter().copied());
+    for (name, functions) in duplicates.iter() {
+        log::error!("{}", clash_message(name, functions));
+    }
+    for function in functions {
+        let clashes = duplicates.iter().any(|(name, _)| name.eq_ignore_ascii_case(function.excel_name));
+        if !clashes {
+            register(&function.registration);
+        }
+    }
+    duplicates.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn function(excel_name: &'static str, rust_name: &'static str) -> XlFunction {
+        XlFunction {
+            excel_name,
+            rust_name,
+            registration: Registration {
+                name: excel_name,
+                type_text: "QQ",
+                arg_text: "x",
+                macro_type: crate::MacroType::Function,
+                category: "",
+                shortcut: "",
+                help_topic: "",
+                help_text: "",
+                arg_help: &["x"],
+            },
+            arguments: &[],
+            return_type: "f64",
+            flags: &[],
+            call: None,
+        }
+    }
+
+    static ADD: XlFunction = function("xl_add", "maths::add");
+    static ADD_UPPER: XlFunction = function("xl_Add", "other::add");
+    static ADD_AGAIN: XlFunction = function("XL_ADD", "more::add");
+    static SUB: XlFunction = function("xl_sub", "maths::sub");
+
+    #[test]
+    fn no_duplicates() {
+        assert!(duplicates([&ADD, &SUB].into_iter()).is_empty());
+    }
+
+    #[test]
+    fn case_insensitive_duplicates() {
+        let duplicates = duplicates([&SUB, &ADD_UPPER, &ADD, &ADD_AGAIN].into_iter());
+        assert_eq!(duplicates, vec![("xl_add".to_string(), vec!["maths::add", "more::add", "other::add"])]);
+        let (name, functions) = &duplicates[0];
+        assert_eq!(
+            clash_message(name, functions),
+            "Excel name xl_add is exported by more than one function: maths::add, more::add, other::add"
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn clashes_are_not_registered() {
+        let excel = crate::mock::start();
+        assert!(!register_functions(&[&ADD, &SUB, &ADD_UPPER]));
+        let registered = excel.registrations().into_iter().map(|r| r.name).collect::<Vec<_>>();
+        assert_eq!(registered, vec!["xl_sub".to_string()]);
+        assert!(register_functions(&[&ADD, &SUB]));
+    }
+}
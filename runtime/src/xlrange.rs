@@ -0,0 +1,235 @@
+use crate::{excel12, XlRef};
+use xladd::variant::Variant;
+use xladd::xlcall::{
+    xlCoerce, xlSheetNm, xlbitDLLFree, xlbitXLFree, xltypeMissing, xltypeNil, xltypeRef, xltypeSRef, LPXLOPER12,
+};
+
+/// A range argument together with where it came from. Functions taking an `XlRange` are
+/// registered with the `U` type so Excel passes the reference rather than the values,
+/// which lets the function report errors such as "input at Sheet1!B3 is invalid".
+///
+/// If the user types a value straight into the formula there is no reference, so
+/// `sheet()` and `row()`/`column()` return `None` but the values are still available.
+#[derive(Debug, Clone)]
+pub struct XlRange {
+    sheet: Option<String>,
+    // First row and column of the reference, zero based
+    origin: Option<(usize, usize)>,
+    // Rows and columns of the reference
+    size: Option<(usize, usize)>,
+    values: Variant,
+    missing: bool,
+}
+
+impl XlRange {
+    /// Build the range from the raw argument Excel passed to a `U` type parameter
+    ///
+    /// # Safety
+    /// `xloper` must be a valid pointer handed to the add-in by Excel for the duration of the call
+    pub unsafe fn from_xloper(xloper: LPXLOPER12) -> XlRange {
+        let xltype = unsafe { (*xloper).xltype } & !(xlbitXLFree | xlbitDLLFree);
+        // First area of the reference. Multiple area references only report the first
+        let area = match xltype {
+            t if t == xltypeSRef => Some(unsafe { (*xloper).val.sref.ref_ }),
+            t if t == xltypeRef => unsafe {
+                let mref = (*xloper).val.mref.lpmref;
+                if mref.is_null() || (*mref).count == 0 {
+                    None
+                } else {
+                    Some((*mref).reftbl[0])
+                }
+            },
+            _ => None,
+        };
+        match area {
+            Some(area) => {
+                let reference = Variant::from(xloper);
+                let sheet = String::try_from(&excel12(xlSheetNm, &mut [reference.clone()])).ok();
+                let values = excel12(xlCoerce, &mut [reference]);
+                XlRange {
+                    sheet,
+                    origin: Some((area.rwFirst as usize, area.colFirst as usize)),
+                    size: Some((
+                        (area.rwLast - area.rwFirst + 1) as usize,
+                        (area.colLast - area.colFirst + 1) as usize,
+                    )),
+                    values,
+                    missing: false,
+                }
+            }
+            None => XlRange {
+                sheet: None,
+                origin: None,
+                size: None,
+                values: Variant::from(xloper),
+                missing: xltype == xltypeMissing || xltype == xltypeNil,
+            },
+        }
+    }
+
+    /// A range of values that didn't come from a reference, as if they had been typed into the formula
+    pub fn from_value(values: Variant) -> XlRange {
+        XlRange {
+            sheet: None,
+            origin: None,
+            size: None,
+            missing: values.is_missing_or_null(),
+            values,
+        }
+    }
+
+    /// True if the argument was omitted
+    pub fn is_missing(&self) -> bool {
+        self.missing
+    }
+
+    /// Name of the sheet the reference points at, as Excel formats it e.g. `[Book1]Sheet1`
+    pub fn sheet(&self) -> Option<&str> {
+        self.sheet.as_deref()
+    }
+
+    /// First row of the reference, zero based
+    pub fn row(&self) -> Option<usize> {
+        self.origin.map(|(row, _)| row)
+    }
+
+    /// First column of the reference, zero based
+    pub fn column(&self) -> Option<usize> {
+        self.origin.map(|(_, column)| column)
+    }
+
+    /// Dimensions as (rows, columns)
+    pub fn dim(&self) -> (usize, usize) {
+        self.size.unwrap_or_else(|| XlRef::from(&self.values).dim())
+    }
+
+    /// The dereferenced values of the range
+    pub fn values(&self) -> XlRef<'_> {
+        XlRef::from(&self.values)
+    }
+
+    /// The A1 style address of the whole range e.g. `Sheet1!B3:D5`
+    pub fn address(&self) -> Option<String> {
+        let (row, column) = self.origin?;
+        let (rows, columns) = self.dim();
+        let first = a1(row, column);
+        let range = if rows * columns > 1 {
+            format!("{}:{}", first, a1(row + rows - 1, column + columns - 1))
+        } else {
+            first
+        };
+        Some(match &self.sheet {
+            Some(sheet) => format!("{}!{}", sheet, range),
+            None => range,
+        })
+    }
+
+    /// The A1 style address of a cell within the range, offsets are zero based
+    pub fn cell_address(&self, row: usize, column: usize) -> Option<String> {
+        let (first_row, first_column) = self.origin?;
+        let cell = a1(first_row + row, first_column + column);
+        Some(match &self.sheet {
+            Some(sheet) => format!("{}!{}", sheet, cell),
+            None => cell,
+        })
+    }
+}
+
+// Zero based row and column to A1 notation
+fn a1(row: usize, column: usize) -> String {
+    let mut letters = Vec::new();
+    let mut column = column + 1;
+    while column > 0 {
+        column -= 1;
+        letters.push((b'A' + (column % 26) as u8) as char);
+        column /= 26;
+    }
+    letters.iter().rev().collect::<String>() + &(row + 1).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XlValue;
+    use xladd::xlcall::xlerrNA;
+
+    fn range(sheet: Option<&str>, origin: (usize, usize), size: (usize, usize)) -> XlRange {
+        XlRange {
+            sheet: sheet.map(str::to_string),
+            origin: Some(origin),
+            size: Some(size),
+            values: Variant::from(1.0),
+            missing: false,
+        }
+    }
+
+    #[test]
+    fn a1_lettering() {
+        assert_eq!(a1(0, 0), "A1");
+        assert_eq!(a1(0, 25), "Z1");
+        assert_eq!(a1(0, 26), "AA1");
+        assert_eq!(a1(0, 701), "ZZ1");
+        assert_eq!(a1(0, 702), "AAA1");
+        assert_eq!(a1(99, 1), "B100");
+    }
+
+    #[test]
+    fn addresses() {
+        let cell = range(Some("Sheet1"), (2, 1), (1, 1));
+        assert_eq!(cell.address().as_deref(), Some("Sheet1!B3"));
+        assert_eq!(cell.cell_address(0, 0).as_deref(), Some("Sheet1!B3"));
+        let block = range(Some("Sheet1"), (2, 1), (3, 3));
+        assert_eq!(block.address().as_deref(), Some("Sheet1!B3:D5"));
+        assert_eq!(block.cell_address(2, 1).as_deref(), Some("Sheet1!C5"));
+        let unnamed = range(None, (0, 25), (1, 2));
+        assert_eq!(unnamed.address().as_deref(), Some("Z1:AA1"));
+        assert_eq!(unnamed.cell_address(0, 1).as_deref(), Some("AA1"));
+    }
+
+    #[test]
+    fn values_without_a_reference() {
+        let values = XlRange::from_value(Variant::from(&XlValue::Array(vec![vec![1.0.into(), 2.0.into()]])));
+        assert_eq!(values.sheet(), None);
+        assert_eq!(values.row(), None);
+        assert_eq!(values.address(), None);
+        assert_eq!(values.cell_address(0, 0), None);
+        assert_eq!(values.dim(), (1, 2));
+        assert!(!values.is_missing());
+    }
+
+    #[test]
+    fn missing_and_errors() {
+        let missing = LPXLOPER12::from(Variant::missing());
+        let missing = unsafe { XlRange::from_xloper(missing) };
+        assert!(missing.is_missing());
+        assert_eq!(missing.address(), None);
+        let error = LPXLOPER12::from(Variant::from_err(xlerrNA));
+        let error = unsafe { XlRange::from_xloper(error) };
+        assert!(!error.is_missing());
+        assert_eq!(error.sheet(), None);
+        assert_eq!(error.dim(), (1, 1));
+        assert_eq!(XlValue::from(error.values().variant()), XlValue::Error(xlerrNA));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn single_reference() {
+        use xladd::xlcall::{XLOPER12, XLREF12};
+        let excel = crate::mock::start();
+        excel.on_call(|function, _| {
+            (function == xlCoerce).then(|| Variant::from(&XlValue::Array(vec![vec![1.0.into(), 2.0.into()], vec![3.0.into(), 4.0.into()]])))
+        });
+        let mut sref: XLOPER12 = unsafe { std::mem::zeroed() };
+        sref.xltype = xltypeSRef;
+        sref.val.sref.count = 1;
+        sref.val.sref.ref_ = XLREF12 { rwFirst: 2, rwLast: 3, colFirst: 1, colLast: 2 };
+        let range = unsafe { XlRange::from_xloper(&mut sref) };
+        assert_eq!(range.sheet(), Some("[Book1]Sheet1"));
+        assert_eq!((range.row(), range.column()), (Some(2), Some(1)));
+        assert_eq!(range.dim(), (2, 2));
+        assert_eq!(range.address().as_deref(), Some("[Book1]Sheet1!B3:C4"));
+        assert_eq!(range.values().to_f64_vec(), Some(vec![1.0, 2.0, 3.0, 4.0]));
+        let calls = excel.calls().iter().map(|call| call.function).collect::<Vec<_>>();
+        assert_eq!(calls, vec![xlSheetNm, xlCoerce]);
+    }
+}
@@ -0,0 +1,148 @@
+use std::convert::TryFrom;
+use xladd::variant::Variant;
+
+/// A borrowed view of an argument exactly as Excel passed it. Use this when the function
+/// needs to decide what to do based on whether it was given a number, a string or a range.
+///
+/// `#[xl_func]` does not convert or check `XlRef` arguments for missing values, so an
+/// omitted argument arrives here with `is_missing()` set.
+#[derive(Clone, Copy)]
+pub struct XlRef<'a>(&'a Variant);
+
+impl<'a> From<&'a Variant> for XlRef<'a> {
+    fn from(v: &'a Variant) -> XlRef<'a> {
+        XlRef(v)
+    }
+}
+
+impl std::fmt::Debug for XlRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl<'a> XlRef<'a> {
+    /// The underlying variant
+    pub fn variant(&self) -> &'a Variant {
+        self.0
+    }
+
+    /// True if the argument was omitted or the cell is empty
+    pub fn is_missing(&self) -> bool {
+        self.0.is_missing_or_null()
+    }
+
+    /// Dimensions as (rows, columns). A single cell is 1x1
+    pub fn dim(&self) -> (usize, usize) {
+        let (columns, rows) = self.0.dim();
+        (rows, columns)
+    }
+
+    /// True if more than one cell was passed
+    pub fn is_range(&self) -> bool {
+        let (rows, columns) = self.dim();
+        rows * columns > 1
+    }
+
+    /// The value as a number if it is a single cell that converts to one
+    pub fn as_f64(&self) -> Option<f64> {
+        if self.is_range() || self.is_missing() {
+            None
+        } else {
+            f64::try_from(self.0).ok()
+        }
+    }
+
+    /// The value as a string if it is a single cell that converts to one
+    pub fn as_string(&self) -> Option<String> {
+        if self.is_range() || self.is_missing() {
+            None
+        } else {
+            String::try_from(self.0).ok()
+        }
+    }
+
+    /// The value as a boolean if it is a single cell that converts to one
+    pub fn as_bool(&self) -> Option<bool> {
+        if self.is_range() || self.is_missing() {
+            None
+        } else {
+            bool::try_from(self.0).ok()
+        }
+    }
+
+    /// All the values in row order as numbers. #N/A and #DIV/0! cells become NaN
+    pub fn to_f64_vec(&self) -> Option<Vec<f64>> {
+        Vec::<f64>::try_from(self.0).ok()
+    }
+
+    /// All the values in row order as strings
+    pub fn to_string_vec(&self) -> Option<Vec<String>> {
+        Vec::<String>::try_from(self.0).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XlValue;
+    use xladd::xlcall::xlerrNA;
+
+    fn variant(value: XlValue) -> Variant {
+        Variant::from(&value)
+    }
+
+    #[test]
+    fn dimensions() {
+        let range = variant(XlValue::Array(vec![vec![1.0.into(), 2.0.into(), 3.0.into()], vec![4.0.into(), 5.0.into(), 6.0.into()]]));
+        let range = XlRef::from(&range);
+        assert_eq!(range.dim(), (2, 3));
+        assert!(range.is_range());
+        assert_eq!(range.to_f64_vec(), Some(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+        let column = variant(XlValue::Array(vec![vec![1.0.into()], vec![2.0.into()]]));
+        assert_eq!(XlRef::from(&column).dim(), (2, 1));
+        let cell = variant(1.0.into());
+        assert_eq!(XlRef::from(&cell).dim(), (1, 1));
+        assert!(!XlRef::from(&cell).is_range());
+    }
+
+    #[test]
+    fn matching_types() {
+        let number = variant(1.5.into());
+        assert_eq!(XlRef::from(&number).as_f64(), Some(1.5));
+        let text = variant("spot".into());
+        assert_eq!(XlRef::from(&text).as_string(), Some("spot".to_string()));
+        let flag = variant(true.into());
+        assert_eq!(XlRef::from(&flag).as_bool(), Some(true));
+    }
+
+    #[test]
+    fn mismatched_types() {
+        let text = variant("spot".into());
+        assert_eq!(XlRef::from(&text).as_f64(), None);
+        // A range is never a single value, even when its cells would convert
+        let range = variant(XlValue::Array(vec![vec!["a".into(), "b".into()]]));
+        let range = XlRef::from(&range);
+        assert_eq!(range.as_string(), None);
+        assert_eq!(range.as_f64(), None);
+        assert_eq!(range.to_string_vec(), Some(vec!["a".to_string(), "b".to_string()]));
+    }
+
+    #[test]
+    fn missing_and_errors() {
+        let missing = variant(XlValue::Missing);
+        let missing = XlRef::from(&missing);
+        assert!(missing.is_missing());
+        assert_eq!(missing.as_f64(), None);
+        assert_eq!(missing.as_string(), None);
+        assert_eq!(missing.as_bool(), None);
+        let empty = variant(XlValue::Empty);
+        assert!(XlRef::from(&empty).is_missing());
+        // An error is a value the function is given, not an omitted argument
+        let error = variant(XlValue::Error(xlerrNA));
+        let error = XlRef::from(&error);
+        assert!(!error.is_missing());
+        assert_eq!(error.dim(), (1, 1));
+        assert_eq!(XlValue::from(error.variant()), XlValue::Error(xlerrNA));
+    }
+}
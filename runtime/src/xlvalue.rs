@@ -0,0 +1,205 @@
+use xladd::variant::Variant;
+use xladd::xlcall::{
+    xlbitDLLFree, xlbitXLFree, xltypeBool, xltypeErr, xltypeInt, xltypeMissing, xltypeMulti, xltypeNil, xltypeNum,
+    xltypeStr, LPXLOPER12, XLOPER12,
+};
+
+/// A value as a worksheet sees it, used to call functions from tests through the generated
+/// `<name>_xl` functions without building XLOPER12s by hand
+///
+/// ```ignore
+/// assert_eq!(add_xl(&[1.0.into(), 2.0.into()]), XlValue::Number(3.0));
+/// assert_eq!(add_xl(&[XlValue::Missing, 2.0.into()]), XlValue::Text("...".into()));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum XlValue {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    /// One of the `xlerr` codes in `xladd::xlcall`, such as `xlerrNA`
+    Error(u32),
+    /// An omitted argument
+    Missing,
+    /// An empty cell
+    Empty,
+    /// Rows of cells
+    Array(Vec<Vec<XlValue>>),
+}
+
+impl XlValue {
+    /// The value as a number if it is one
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            XlValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The value as text if it is text
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            XlValue::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The Excel error code if the value is an error
+    pub fn as_error(&self) -> Option<u32> {
+        match self {
+            XlValue::Error(e) => Some(*e),
+            _ => None,
+        }
+    }
+
+    // Read an XLOPER12 built by Excel or xladd
+    unsafe fn from_xloper(xloper: &XLOPER12) -> XlValue {
+        let xltype = xloper.xltype & !(xlbitXLFree | xlbitDLLFree);
+        unsafe {
+            match xltype {
+                t if t == xltypeNum => XlValue::Number(xloper.val.num),
+                t if t == xltypeInt => XlValue::Number(xloper.val.w as f64),
+                t if t == xltypeBool => XlValue::Bool(xloper.val.xbool != 0),
+                t if t == xltypeErr => XlValue::Error(xloper.val.err as u32),
+                t if t == xltypeMissing => XlValue::Missing,
+                t if t == xltypeNil => XlValue::Empty,
+                // Length prefixed UTF-16
+                t if t == xltypeStr => {
+                    let s = xloper.val.str;
+                    if s.is_null() {
+                        XlValue::Text(String::new())
+                    } else {
+                        let len = *s as usize;
+                        XlValue::Text(String::from_utf16_lossy(std::slice::from_raw_parts(s.add(1), len)))
+                    }
+                }
+                t if t == xltypeMulti => {
+                    let array = xloper.val.array;
+                    let (rows, columns) = (array.rows.max(0) as usize, array.columns.max(0) as usize);
+                    let cells = if array.lparray.is_null() { &[][..] } else { std::slice::from_raw_parts(array.lparray, rows * columns) };
+                    XlValue::Array(cells.chunks(columns.max(1)).map(|row| row.iter().map(|cell| XlValue::from_xloper(cell)).collect()).collect())
+                }
+                _ => XlValue::Error(xladd::xlcall::xlerrValue),
+            }
+        }
+    }
+}
+
+impl From<&Variant> for XlValue {
+    fn from(v: &Variant) -> XlValue {
+        // The copy handed out as an XLOPER12 is never freed, as if Excel had not called xlAutoFree12.
+        // This is only used from tests
+        let xloper = LPXLOPER12::from(v.clone());
+        unsafe { XlValue::from_xloper(&*xloper) }
+    }
+}
+
+/// Arrays are passed as numbers if every cell is a number and as text otherwise
+impl From<&XlValue> for Variant {
+    fn from(v: &XlValue) -> Variant {
+        match v {
+            XlValue::Number(n) => Variant::from(*n),
+            XlValue::Text(s) => Variant::from(s.as_str()),
+            XlValue::Bool(b) => Variant::from(*b),
+            XlValue::Error(e) => Variant::from_err(*e),
+            XlValue::Missing => Variant::missing(),
+            XlValue::Empty => Variant::default(),
+            XlValue::Array(rows) => {
+                let columns = rows.first().map(Vec::len).unwrap_or(0);
+                let cells = rows.iter().flatten().collect::<Vec<_>>();
+                match cells.iter().map(|cell| cell.as_f64()).collect::<Option<Vec<_>>>() {
+                    Some(numbers) => Variant::from(&(numbers.as_slice(), columns)),
+                    None => {
+                        let text = cells.iter().map(|cell| cell.to_string()).collect::<Vec<_>>();
+                        let text = text.iter().map(String::as_str).collect::<Vec<_>>();
+                        Variant::from(&(text.as_slice(), columns))
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl From<f64> for XlValue {
+    fn from(n: f64) -> XlValue {
+        XlValue::Number(n)
+    }
+}
+
+impl From<bool> for XlValue {
+    fn from(b: bool) -> XlValue {
+        XlValue::Bool(b)
+    }
+}
+
+impl From<&str> for XlValue {
+    fn from(s: &str) -> XlValue {
+        XlValue::Text(s.to_string())
+    }
+}
+
+impl From<String> for XlValue {
+    fn from(s: String) -> XlValue {
+        XlValue::Text(s)
+    }
+}
+
+/// Formatted as it would be typed into a formula, e.g. `{1,2;3,4}` for an array
+impl std::fmt::Display for XlValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XlValue::Number(n) => write!(f, "{}", n),
+            XlValue::Text(s) => write!(f, "{}", s),
+            XlValue::Bool(b) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            XlValue::Error(e) => write!(f, "{}", error_name(*e)),
+            XlValue::Missing | XlValue::Empty => Ok(()),
+            XlValue::Array(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| row.iter().map(|cell| cell.to_string()).collect::<Vec<_>>().join(","))
+                    .collect::<Vec<_>>();
+                write!(f, "{{{}}}", rows.join(";"))
+            }
+        }
+    }
+}
+
+/// The text Excel shows for an error code, e.g. `#N/A` for `xlerrNA`
+pub fn error_name(xlerr: u32) -> &'static str {
+    use xladd::xlcall::{xlerrDiv0, xlerrNA, xlerrName, xlerrNull, xlerrNum, xlerrRef, xlerrValue};
+    match xlerr {
+        e if e == xlerrNull => "#NULL!",
+        e if e == xlerrDiv0 => "#DIV/0!",
+        e if e == xlerrValue => "#VALUE!",
+        e if e == xlerrRef => "#REF!",
+        e if e == xlerrName => "#NAME?",
+        e if e == xlerrNum => "#NUM!",
+        e if e == xlerrNA => "#N/A",
+        _ => "#GETTING_DATA",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xladd::xlcall::xlerrNA;
+
+    fn round_trip(value: XlValue) -> XlValue {
+        XlValue::from(&Variant::from(&value))
+    }
+
+    #[test]
+    fn scalars_round_trip() {
+        for value in [XlValue::Number(1.5), "spot".into(), true.into(), XlValue::Error(xlerrNA), XlValue::Missing] {
+            assert_eq!(round_trip(value.clone()), value);
+        }
+    }
+
+    #[test]
+    fn arrays_round_trip() {
+        let numbers = XlValue::Array(vec![vec![1.0.into(), 2.0.into()], vec![3.0.into(), 4.0.into()]]);
+        assert_eq!(round_trip(numbers.clone()), numbers);
+        assert_eq!(numbers.to_string(), "{1,2;3,4}");
+        let mixed = XlValue::Array(vec![vec![1.0.into(), "a".into()]]);
+        assert_eq!(round_trip(mixed), XlValue::Array(vec![vec!["1".into(), "a".into()]]));
+    }
+}
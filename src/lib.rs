@@ -1,42 +1,705 @@
-use proc_macro::*;
+use proc_macro::TokenStream;
+use proc_macro2::TokenTree;
 use std::collections::BTreeMap;
 use quote::quote;
+use syn::spanned::Spanned;
 use syn::{FnArg, ItemFn};
 
+// Parameter attributes of the form #[xl(key = "value", flag)]. Flags are stored with an empty value
+fn xl_param_attrs(attrs: &[syn::Attribute]) -> BTreeMap<String, String> {
+    let mut params = BTreeMap::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("xl")) {
+        match attr.parse_meta() {
+            Ok(syn::Meta::List(list)) => {
+                for nested in list.nested.iter() {
+                    match nested {
+                        syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) => match &nv.lit {
+                            syn::Lit::Str(s) => {
+                                params.insert(nv.path.get_ident().map(|i| i.to_string()).unwrap_or_default(), s.value());
+                            }
+                            // count = 10
+                            syn::Lit::Int(i) => {
+                                params.insert(nv.path.get_ident().map(|i| i.to_string()).unwrap_or_default(), i.base10_digits().to_string());
+                            }
+                            _ => panic!("#[xl(...)] values must be string or integer literals"),
+                        },
+                        syn::NestedMeta::Meta(syn::Meta::Path(p)) => {
+                            params.insert(p.get_ident().map(|i| i.to_string()).unwrap_or_default(), String::new());
+                        }
+                        _ => panic!("Expected #[xl(key = \"value\")]"),
+                    }
+                }
+            }
+            _ => panic!("Expected #[xl(key = \"value\")]"),
+        }
+    }
+    params
+}
 
+// The #[xl(...)] attributes are only for the macro, so remove them before emitting the user function
+fn strip_xl_attrs(item: &mut ItemFn) {
+    for arg in item.sig.inputs.iter_mut() {
+        if let FnArg::Typed(typed_arg) = arg {
+            typed_arg.attrs.retain(|attr| !attr.path.is_ident("xl"));
+        }
+    }
+}
 
-#[proc_macro_attribute]
-pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
-    // println!("{:?}", attr);
-    // println!("{:?}", input);
-    // println!("{:?}", input);
+// Validate the dimensions of a range before it is flattened into a slice
+fn shape_check(func: &syn::Ident, arg_name: &proc_macro2::TokenStream, shape: Option<&String>) -> proc_macro2::TokenStream {
+    let (test, expected) = match shape.map(String::as_str) {
+        None => return quote!(),
+        Some("column") => (quote!(columns != 1), "a single column"),
+        Some("row") => (quote!(rows != 1), "a single row"),
+        Some("vector") => (quote!(rows != 1 && columns != 1), "a single row or column"),
+        Some(s) => panic!("Unknown shape \"{}\", expected one of column, row or vector", s),
+    };
+    quote!(
+        {
+            let (columns, rows) = #arg_name.dim();
+            if #test {
+                return Err(format!("{} expects {} for argument {} but was given a {}x{} range", stringify!(#func), #expected, stringify!(#arg_name), rows, columns).into());
+            }
+        }
+    )
+}
+
+// Variant and XlRef arguments are handed to the user function as Excel passed them, without conversion or missing checks
+fn passthrough_type(ty: &syn::Type) -> Option<String> {
+    let ty = match ty {
+        syn::Type::Reference(r) => &*r.elem,
+        ty => ty,
+    };
+    match ty {
+        syn::Type::Path(p) => {
+            let ident = &p.path.segments.last()?.ident;
+            if ident == "Variant" || ident == "XlRef" {
+                Some(ident.to_string())
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// Number of optional Q slots registered for a trailing #[xl(variadic)] argument
+fn variadic_count(xl_attrs: &BTreeMap<String, String>) -> Option<usize> {
+    if !xl_attrs.contains_key("variadic") {
+        return None;
+    }
+    match xl_attrs.get("count") {
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) if count > 0 => Some(count),
+            _ => panic!("variadic count must be a positive number, got \"{}\"", count),
+        },
+        None => Some(30),
+    }
+}
+
+// Collect the non missing variadic slots into a single Vec, each slot can be a single value or a range
+fn variadic_conversion(arg_name: &proc_macro2::TokenStream, ty: &syn::Type, trace_arg: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let elem = match ty {
+        syn::Type::Reference(r) => match &*r.elem {
+            syn::Type::Slice(s) => &*s.elem,
+            _ => panic!("variadic arguments must be slices such as &[f64] or &[&str]"),
+        },
+        _ => panic!("variadic arguments must be slices such as &[f64] or &[&str]"),
+    };
+    let (owned, as_ref) = match elem {
+        syn::Type::Reference(r) => match &*r.elem {
+            syn::Type::Path(p) if p.path.segments[0].ident == "str" => (
+                quote!(String),
+                quote!(let #arg_name = #arg_name.iter().map(AsRef::as_ref).collect::<Vec<_>>();),
+            ),
+            _ => panic!("Only slices of &[&str] supported"),
+        },
+        syn::Type::Path(p) => (quote!(#p), quote!()),
+        _ => panic!("Type not covered"),
+    };
+    quote!(
+        let #arg_name = {
+            let mut __xladd_values = Vec::new();
+            for __xladd_value in #arg_name.iter().filter(|__xladd_value| !__xladd_value.is_missing_or_null()) {
+                __xladd_values.extend(std::convert::TryInto::<Vec<#owned>>::try_into(__xladd_value)?);
+            }
+            __xladd_values
+        };
+        #as_ref
+        #trace_arg
+    )
+}
+
+// XlRange arguments are built from the raw reference rather than a Variant
+fn is_range_type(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(p) => p.path.segments.last().map(|s| s.ident == "XlRange").unwrap_or(false),
+        _ => false,
+    }
+}
+
+// Fixed size arrays [T; N] and [[T; C]; R] are checked for an exact size so the user function can index them safely
+fn fixed_array(func: &syn::Ident, arg_name: &proc_macro2::TokenStream, array: &syn::TypeArray) -> proc_macro2::TokenStream {
+    let basic_type = |ty: &syn::Type| match ty {
+        syn::Type::Path(p) => {
+            if p.path.segments[0].ident == "str" {
+                panic!("Arrays of &str are not supported, use [String; N]")
+            }
+            quote!(#p)
+        }
+        _ => panic!("Only arrays of f64,i64,bool,String or arrays of arrays of those types are supported"),
+    };
+    match &*array.elem {
+        syn::Type::Array(inner) => {
+            let rows = &array.len;
+            let columns = &inner.len;
+            let elem = basic_type(&inner.elem);
+            quote!(
+                {
+                    let (columns, rows) = #arg_name.dim();
+                    if rows != #rows || columns != #columns {
+                        return Err(format!("{} expected a {}x{} range for argument {}, got {}x{}", stringify!(#func), #rows, #columns, stringify!(#arg_name), rows, columns).into());
+                    }
+                }
+                let #arg_name = std::convert::TryInto::<Vec<#elem>>::try_into(&#arg_name)?;
+                if #arg_name.len() != #rows * #columns {
+                    return Err(format!("{} expected {} values for argument {}, got {}", stringify!(#func), #rows * #columns, stringify!(#arg_name), #arg_name.len()).into());
+                }
+                let #arg_name: [[#elem; #columns]; #rows] = std::array::from_fn(|__xladd_row| std::array::from_fn(|__xladd_column| #arg_name[__xladd_row * #columns + __xladd_column].clone()));
+            )
+        }
+        elem => {
+            let len = &array.len;
+            let elem = basic_type(elem);
+            quote!(
+                let #arg_name = std::convert::TryInto::<Vec<#elem>>::try_into(&#arg_name)?;
+                let #arg_name: [#elem; #len] = std::convert::TryInto::try_into(#arg_name)
+                    .map_err(|v: Vec<#elem>| format!("{} expected {} values for argument {}, got {}", stringify!(#func), #len, stringify!(#arg_name), v.len()))?;
+            )
+        }
+    }
+}
+
+// Excel limits on registration, xlfRegister fails silently when these are exceeded so check them at compile time.
+// xlfRegister takes at most 255 opers, 10 of them fixed and then one help text for each argument
+const MAX_ARGUMENTS: usize = 245;
+const MAX_TEXT_LENGTH: usize = 255;
+
+// The exported name doubles as a Rust identifier and an Excel function name so it has to be valid as both
+fn validate_excel_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Excel function name cannot be empty".to_string());
+    }
+    if name.chars().count() > MAX_TEXT_LENGTH {
+        return Err(format!("Excel function name {} is longer than {} characters", name, MAX_TEXT_LENGTH));
+    }
+    if let Some(c) = name.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '_')) {
+        return Err(format!("Excel function name {} contains '{}', only letters, digits and _ are allowed", name, c));
+    }
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(format!("Excel function name {} cannot start with a digit", name));
+    }
+    Ok(())
+}
+
+// The Cargo.toml of the crate being compiled
+fn manifest_path() -> Option<std::path::PathBuf> {
+    let dir = std::env::var("CARGO_MANIFEST_DIR").ok()?;
+    Some(std::path::Path::new(&dir).join("Cargo.toml")).filter(|manifest| manifest.is_file())
+}
+
+// Settings already read, by manifest and the time it was modified so an edit is picked up by a long
+// running compiler such as rust-analyzer
+type SettingsCache = BTreeMap<(std::path::PathBuf, Option<std::time::SystemTime>), BTreeMap<String, String>>;
+static SETTINGS: std::sync::Mutex<SettingsCache> = std::sync::Mutex::new(BTreeMap::new());
+
+// Crate wide settings from the [package.metadata.xladd] table in the Cargo.toml of the crate being compiled.
+// Every expansion asks for them, so the manifest is only read and parsed once
+fn crate_settings() -> BTreeMap<String, String> {
+    let manifest = match manifest_path() {
+        Some(manifest) => manifest,
+        None => return BTreeMap::new(),
+    };
+    let modified = std::fs::metadata(&manifest).and_then(|m| m.modified()).ok();
+    let mut cache = SETTINGS.lock().unwrap_or_else(|e| e.into_inner());
+    cache
+        .entry((manifest.clone(), modified))
+        .or_insert_with(|| read_settings(&manifest))
+        .clone()
+}
+
+fn read_settings(manifest: &std::path::Path) -> BTreeMap<String, String> {
+    let manifest = match std::fs::read_to_string(manifest).map(|s| s.parse::<toml::Table>()) {
+        Ok(Ok(manifest)) => manifest,
+        _ => return BTreeMap::new(),
+    };
+    manifest
+        .get("package")
+        .and_then(|p| p.get("metadata"))
+        .and_then(|m| m.get("xladd"))
+        .and_then(|x| x.as_table())
+        .map(|settings| {
+            settings
+                .iter()
+                .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Cargo doesn't know the macros read the manifest, so include it in the generated code to have the crate
+// rebuilt when [package.metadata.xladd] changes
+fn track_manifest() -> proc_macro2::TokenStream {
+    match manifest_path().and_then(|manifest| manifest.to_str().map(String::from)) {
+        Some(manifest) => quote!(const _: &[u8] = include_bytes!(#manifest);),
+        None => quote!(),
+    }
+}
+
+// The exported name of a function, with the crate wide unique_prefix if there is one
+fn exported_name(prefix: &str, rename: &str) -> String {
+    match crate_settings().get("unique_prefix") {
+        Some(unique_prefix) => format!("{}_{}_{}", unique_prefix, prefix, rename),
+        None => format!("{}_{}", prefix, rename),
+    }
+}
+
+// The "Help on this function" link of a function, from its help_topic or the crate wide help_topic
+// template. {base} is replaced by the crate wide help_base and {name} by the Excel name
+fn help_topic(topic: Option<&str>, xl_name: &str) -> Result<String, String> {
+    let settings = crate_settings();
+    let topic = match topic.or(settings.get("help_topic").map(String::as_str)) {
+        Some(topic) => topic,
+        None => return Ok(String::new()),
+    };
+    let topic = match settings.get("help_base") {
+        Some(base) => topic.replace("{base}", base.trim_end_matches('/')),
+        None if topic.contains("{base}") => return Err("help_topic uses {base} but there is no help_base in [package.metadata.xladd]".to_string()),
+        None => topic.to_string(),
+    };
+    validate_help_topic(&topic.replace("{name}", xl_name))
+}
+
+// Excel accepts a URL or a topic in a compiled help file, both followed by !<topic id>. URLs
+// are given a !0 if they don't have one
+fn validate_help_topic(topic: &str) -> Result<String, String> {
+    let expected = "help_topic must be a URL such as https://intranet/docs/xl_price or a help file topic such as help.chm!100";
+    if topic.chars().any(char::is_whitespace) || topic.contains(['{', '}']) {
+        return Err(format!("Invalid help_topic \"{}\", {}", topic, expected));
+    }
+    let topic_id = |s: &str| s.rsplit_once('!').filter(|(_, id)| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit())).map(|(file, _)| file.to_string());
+    if let Some(rest) = topic.strip_prefix("https://").or_else(|| topic.strip_prefix("http://")) {
+        if rest.is_empty() || rest.starts_with('/') {
+            return Err(format!("Invalid help_topic \"{}\", the URL has no host", topic));
+        }
+        return Ok(if topic_id(topic).is_some() { topic.to_string() } else { format!("{}!0", topic) });
+    }
+    match topic_id(topic) {
+        Some(file) if file.len() > 4 && [".chm", ".hlp"].iter().any(|ext| file.to_ascii_lowercase().ends_with(ext)) => Ok(topic.to_string()),
+        _ => Err(format!("Invalid help_topic \"{}\", {}", topic, expected)),
+    }
+}
+
+// What a caught panic hands back to Excel, from the function's on_panic or the crate wide default.
+// "message" shows the panic message in the cell, "error" shows #NUM! and "abort" takes Excel down in
+// debug builds, so the panic can't be missed, and behaves as "message" in release builds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OnPanic {
+    Message,
+    Error,
+    Abort,
+}
+
+fn on_panic(setting: Option<&str>) -> Result<OnPanic, String> {
+    let settings = crate_settings();
+    match setting.or(settings.get("on_panic").map(String::as_str)).unwrap_or("message") {
+        "message" => Ok(OnPanic::Message),
+        "error" => Ok(OnPanic::Error),
+        "abort" => Ok(OnPanic::Abort),
+        other => Err(format!("Invalid on_panic \"{}\", expected \"error\", \"message\" or \"abort\"", other)),
+    }
+}
+
+// The match arm for a panic caught by catch_panic. `handle` is given the Variant for Excel and
+// returns the body of the arm
+fn panic_arm(on_panic: OnPanic, handle: impl Fn(proc_macro2::TokenStream) -> proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let message = quote!(xladd::variant::Variant::from(panic.to_string()));
+    match on_panic {
+        OnPanic::Message => {
+            let handled = handle(message);
+            quote!(Err(panic) => { #handled })
+        }
+        OnPanic::Error => {
+            let handled = handle(quote!(xladd::variant::Variant::from_err(xladd::xlcall::xlerrNum)));
+            quote!(Err(_) => { #handled })
+        }
+        OnPanic::Abort => {
+            let handled = handle(message);
+            quote!(Err(panic) => {
+                if cfg!(debug_assertions) {
+                    std::process::abort();
+                }
+                #handled
+            })
+        }
+    }
+}
+
+// The registration of a function, its register_<name> function and its entry in the add-in wide registry
+// so it can be registered from xlAutoOpen and checked for duplicates. Excel names are case insensitive, so
+// a second function exporting the same name in a different case fails to link on the lower cased symbol
+fn registry_entry(
+    xl_name: &str,
+    func: &syn::Ident,
+    register_function: &proc_macro2::Ident,
+    registration: proc_macro2::TokenStream,
+    details: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let symbol = format!("xladd_excel_name_{}", xl_name.to_ascii_lowercase());
+    let upper = func.to_string().to_ascii_uppercase();
+    let marker = proc_macro2::Ident::new(&format!("_XLADD_EXCEL_NAME_{}", upper), proc_macro2::Span::call_site());
+    let registration_const = proc_macro2::Ident::new(&format!("_XLADD_REGISTRATION_{}", upper), proc_macro2::Span::call_site());
+    quote! {
+        #[allow(non_upper_case_globals)]
+        const #registration_const: xladd_derive_runtime::Registration<'static> = xladd_derive_runtime::Registration {
+            name: #xl_name,
+            #registration
+        };
+        // The Reg argument is kept so existing xlAutoOpen functions continue to compile
+        pub (crate) fn #register_function(_reg: &xladd::registrator::Reg) {
+            xladd_derive_runtime::register(&#registration_const);
+        }
+        #[unsafe(export_name = #symbol)]
+        #[allow(non_upper_case_globals)]
+        static #marker: u8 = 0;
+        xladd_derive_runtime::inventory::submit! {
+            xladd_derive_runtime::XlFunction {
+                excel_name: #xl_name,
+                rust_name: concat!(module_path!(), "::", stringify!(#func)),
+                registration: #registration_const,
+                #details
+            }
+        }
+    }
+}
+
+// A type as written in the source, without the spaces the token printer puts around punctuation
+fn type_name(ty: &syn::Type) -> String {
+    let text = quote!(#ty).to_string();
+    let chars = text.chars().collect::<Vec<_>>();
+    let word = |c: Option<&char>| c.map(|c| c.is_alphanumeric() || *c == '_').unwrap_or(false);
+    chars
+        .iter()
+        .enumerate()
+        .filter(|(i, c)| **c != ' ' || (word(chars.get(i.wrapping_sub(1))) && word(chars.get(i + 1))))
+        .map(|(_, c)| c)
+        .collect()
+}
+
+// The type a function hands back to Excel, the Ok type of a Result
+fn return_type_name(output: &syn::ReturnType) -> String {
+    match output {
+        syn::ReturnType::Default => String::new(),
+        syn::ReturnType::Type(_, ty) => match &**ty {
+            syn::Type::Path(p) => match p.path.segments.last() {
+                Some(segment) if segment.ident == "Result" => match &segment.arguments {
+                    syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+                        Some(syn::GenericArgument::Type(ok)) => type_name(ok),
+                        _ => type_name(ty),
+                    },
+                    _ => type_name(ty),
+                },
+                _ => type_name(ty),
+            },
+            _ => type_name(ty),
+        },
+    }
+}
 
-    let item = syn::parse::<ItemFn>(input).expect("Failed to parse.");
+// Proc macros can't raise warnings on stable, but using a deprecated item can. The use is spanned to the
+// parameter so the warning points at it. The item is declared in an anonymous const so it can't clash with
+// the marker of another function, as price(spot_vol) and price_spot(vol) would
+fn undocumented_warning(func: &syn::Ident, arg: &str, span: proc_macro2::Span) -> proc_macro2::TokenStream {
+    let note = format!("argument `{}` of `{}` has no `* {} - ...` line in the doc comment, Excel will show its name and type as the help", arg, func, arg);
+    let name = format!("_XLADD_UNDOCUMENTED_{}_{}", func.to_string().to_ascii_uppercase(), arg.to_ascii_uppercase());
+    let marker = proc_macro2::Ident::new(&name, proc_macro2::Span::call_site());
+    let usage = proc_macro2::Ident::new(&name, span);
+    quote! {
+        const _: () = {
+            #[deprecated(note = #note)]
+            #[allow(non_upper_case_globals)]
+            const #marker: () = ();
+            #usage
+        };
+    }
+}
+
+// Function documentation taken from the doc comments
+//
+//     /// Description which can run over
+//     /// several lines and paragraphs
+//     /// * arg1 - Help for arg1, `- arg1 -` and `* arg1:` also work
+//     ///   and indented lines continue the help
+//     /// * ret - Appended to the description
+struct DocComments {
+    description: String,
+    ret: String,
+    // Argument name to help text, with the span of the doc comment for errors
+    args: BTreeMap<String, (String, proc_macro2::Span)>,
+}
+
+fn parse_doc_comments(attrs: &[syn::Attribute]) -> DocComments {
+    let mut description = Vec::new();
+    let mut args: Vec<(String, String, proc_macro2::Span)> = Vec::new();
+    let mut in_arg = false;
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("doc")) {
+        let doc = match attr.parse_meta() {
+            Ok(syn::Meta::NameValue(syn::MetaNameValue { lit: syn::Lit::Str(doc), .. })) => doc.value(),
+            _ => continue,
+        };
+        // A /** */ block comment arrives as a single attribute with embedded newlines
+        for line in doc.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                in_arg = false;
+                continue;
+            }
+            if let Some((name, help)) = doc_argument(trimmed) {
+                args.push((name, help.to_string(), attr.span()));
+                in_arg = true;
+            } else if in_arg && line.starts_with("  ") {
+                // Indented continuation of the previous argument
+                if let Some((_, help, _)) = args.last_mut() {
+                    help.push(' ');
+                    help.push_str(trimmed);
+                }
+            } else {
+                in_arg = false;
+                description.push(trimmed.trim_start_matches('#').trim().to_string());
+            }
+        }
+    }
+    let mut args = args
+        .into_iter()
+        .map(|(name, help, span)| (name, (strip_markdown(&help), span)))
+        .collect::<BTreeMap<_, _>>();
+    let ret = args.remove("ret").map(|(ret, _)| ret).unwrap_or_default();
+    DocComments {
+        description: strip_markdown(&description.join(" ")),
+        ret,
+        args,
+    }
+}
+
+// An argument line `* name - help`, `* name: help`, `- name - help` or `- name: help`. The name can be in backticks
+fn doc_argument(line: &str) -> Option<(String, &str)> {
+    let rest = line.strip_prefix("* ").or_else(|| line.strip_prefix("- "))?.trim_start();
+    let (name, rest) = match rest.strip_prefix('`') {
+        Some(quoted) => {
+            let end = quoted.find('`')?;
+            (&quoted[..end], &quoted[end + 1..])
+        }
+        None => {
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        }
+    };
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let help = rest.strip_prefix('-').or_else(|| rest.strip_prefix(':'))?;
+    Some((name.to_string(), help.trim()))
+}
+
+// Excel shows plain text so remove backticks, emphasis and links, and collapse the whitespace
+fn strip_markdown(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '`' => (),
+            // [text](url) and [text] keep the text
+            '[' => {
+                if let Some(close) = chars[i + 1..].iter().position(|c| *c == ']').map(|p| p + i + 1) {
+                    out.extend(&chars[i + 1..close]);
+                    i = close + 1;
+                    if chars.get(i) == Some(&'(')
+                        && let Some(end) = chars[i..].iter().position(|c| *c == ')')
+                    {
+                        i += end + 1;
+                    }
+                    continue;
+                }
+                out.push(c);
+            }
+            // Emphasis markers open before a word or close after one, so spot_price and 2 * 3 are left alone
+            '*' | '_' => {
+                let prev = if i == 0 { None } else { Some(chars[i - 1]) };
+                let mut end = i;
+                while end < chars.len() && chars[end] == c {
+                    end += 1;
+                }
+                let next = chars.get(end).cloned();
+                let word = |c: Option<char>| c.map(|c| c.is_alphanumeric()).unwrap_or(false);
+                let space = |c: Option<char>| c.map(char::is_whitespace).unwrap_or(true);
+                let opens = !word(prev) && !space(next);
+                let closes = !space(prev) && !word(next);
+                if !(opens || closes) {
+                    out.extend(&chars[i..end]);
+                }
+                i = end;
+                continue;
+            }
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Attribute arguments of the form key = "value", or a bare flag which is stored with an empty value
+fn parse_params(attr: proc_macro2::TokenStream) -> BTreeMap<String, String> {
     let mut params = BTreeMap::new();
     let tree = attr.into_iter().collect::<Vec<TokenTree>>();
     for chunk in tree.as_slice().windows(3) {
         match chunk {
             [TokenTree::Ident(i),TokenTree::Punct(_),TokenTree::Literal(l)] => {
-                let l = l.to_string();
-                params.insert(i.to_string(),l[1..l.len()-1].to_string());
+                // The value of the literal, with escapes such as \" undone
+                let value = match syn::Lit::new(l.clone()) {
+                    syn::Lit::Str(s) => s.value(),
+                    lit => quote!(#lit).to_string(),
+                };
+                params.insert(i.to_string(),value);
+            },
+            // trace = false
+            [TokenTree::Ident(i),TokenTree::Punct(p),TokenTree::Ident(b)] if p.as_char() == '=' && (b == "true" || b == "false") => {
+                params.insert(i.to_string(),b.to_string());
             },
             _ => (),
         }
     }
+    // Flags such as `volatile` are a bare ident not followed by `=`
+    for (i, token) in tree.iter().enumerate() {
+        if let TokenTree::Ident(ident) = token {
+            let is_eq = |t: Option<&TokenTree>| matches!(t, Some(TokenTree::Punct(p)) if p.as_char() == '=');
+            match tree.get(i + 1) {
+                _ if i > 0 && is_eq(tree.get(i - 1)) => (),
+                next if is_eq(next) => (),
+                _ => {
+                    params.entry(ident.to_string()).or_insert_with(String::new);
+                }
+            }
+        }
+    }
+    params
+}
+
+// Function modifiers that are appended to the type text registered with Excel
+#[derive(Default)]
+struct Modifiers {
+    async_function: bool,
+    thread_safe: bool,
+    volatile: bool,
+    macro_equivalent: bool,
+    cluster_safe: bool,
+}
+
+// The xlfRegister type text, the return type and argument codes followed by the modifiers. Combinations
+// Excel rejects are returned as errors to be reported against the attribute
+fn type_text(arg_codes: &str, modifiers: &Modifiers) -> Result<String, String> {
+    if modifiers.volatile && modifiers.async_function {
+        return Err("volatile cannot be combined with async, the result of an async function arrives after the recalculation it was started by".to_string());
+    }
+    if modifiers.macro_equivalent && modifiers.thread_safe {
+        return Err("macro_equivalent functions cannot be thread safe, Excel rejects # combined with $".to_string());
+    }
+    if modifiers.macro_equivalent && modifiers.async_function {
+        return Err("macro_equivalent cannot be combined with async".to_string());
+    }
+    if modifiers.macro_equivalent && modifiers.cluster_safe {
+        return Err("macro_equivalent functions call back into Excel so they cannot be cluster_safe".to_string());
+    }
+    if modifiers.cluster_safe && arg_codes.contains('U') {
+        return Err("cluster_safe functions cannot take reference arguments such as XlRange".to_string());
+    }
+    let mut type_text = String::new();
+    // Mark function as async
+    if modifiers.async_function {
+        type_text.push('>');
+        type_text.push_str(arg_codes);
+        type_text.push('X');
+    } else {
+        // Return type is a variant, Excel reads it from the first code
+        type_text.push('Q');
+        type_text.push_str(arg_codes);
+    }
+    if modifiers.volatile {
+        type_text.push('!');
+    }
+    if modifiers.macro_equivalent {
+        type_text.push('#');
+    }
+    if modifiers.cluster_safe {
+        type_text.push('&');
+    }
+    if modifiers.thread_safe {
+        type_text.push('$');
+    }
+    Ok(type_text)
+}
+
+#[proc_macro_attribute]
+pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let mut expanded = expand_xl_func(attr.into(), input.into());
+    expanded.extend(track_manifest());
+    expanded.into()
+}
+
+// The code generation behind #[xl_func], on proc_macro2 tokens so it can be run from tests
+fn expand_xl_func(attr: proc_macro2::TokenStream, input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let item = syn::parse2::<ItemFn>(input).expect("Failed to parse.");
+    let params = parse_params(attr.clone());
     let name = item.sig.ident.to_string();
     let category = if let Some(v) = params.get("category") { v } else { "" };
     let prefix = if let Some(v) = params.get("prefix") { v } else { "xl" };
     let rename = if let Some(v) = params.get("rename") { v } else { name.as_str() };
     let async_function = if let Some(_) = params.get("async") { true } else { false };
-    let single_threaded = if let Some(_) = params.get("single_threaded") { true } else { true };
+    // Async functions are never registered thread safe
+    let thread_safe = params.contains_key("thread_safe") && !async_function;
+    let volatile = params.contains_key("volatile");
+    let macro_equivalent = params.contains_key("macro_equivalent");
+    let cluster_safe = params.contains_key("cluster_safe");
+    // Hidden functions are registered with macro type 0 so they don't appear in the Function Wizard
+    let macro_type = if params.contains_key("hidden") {
+        quote!(xladd_derive_runtime::MacroType::Hidden)
+    } else {
+        quote!(xladd_derive_runtime::MacroType::Function)
+    };
     // Use `quote` to convert the syntax tree back into tokens so we can return them. Note
     // that the tokens we're returning at this point are still just the input, we've simply
     // converted it between a few different forms.
     let output = &item.sig.output;
     let func = &item.sig.ident;
 
+    let xl_name = exported_name(prefix, rename);
+    if let Err(e) = validate_excel_name(&xl_name) {
+        return syn::Error::new(func.span(), e).to_compile_error();
+    }
+    let help_topic = match help_topic(params.get("help_topic").map(String::as_str), &xl_name) {
+        Ok(help_topic) => help_topic,
+        Err(e) => return syn::Error::new(func.span(), e).to_compile_error(),
+    };
+    let on_panic = match on_panic(params.get("on_panic").map(String::as_str)) {
+        Ok(on_panic) => on_panic,
+        Err(e) => return syn::Error::new(func.span(), e).to_compile_error(),
+    };
+    // trace = false leaves out the span, or the trace logging, of every call
+    let trace = params.get("trace").map(|v| v != "false").unwrap_or(true);
     let xl_function = proc_macro2::Ident::new(
-        &format!("{}_{}", prefix, rename),
+        &xl_name,
         proc_macro2::Span::call_site(),
     );
     let error_handler_function = proc_macro2::Ident::new(
@@ -62,10 +725,39 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                     _ => panic!("Type not covered"),
                 }
             };
+            let xl_attrs = xl_param_attrs(&typed_arg.attrs);
+            let trace_arg = if trace {
+                let field = arg_name.to_string();
+                let field = field.trim_start_matches("r#");
+                quote!(__xladd_invocation.argument(#field, &#arg_name);)
+            } else {
+                quote!()
+            };
+            let shape_check = shape_check(func, &arg_name, xl_attrs.get("shape"));
+            if xl_attrs.contains_key("shape") && !matches!(&*typed_arg.ty, syn::Type::Reference(r) if matches!(&*r.elem, syn::Type::Slice(_))) {
+                panic!("shape is only supported on slice arguments such as &[f64]");
+            }
+            // Each slot of a variadic argument is a separate value or range, so there is no one shape to check
+            if xl_attrs.contains_key("shape") && xl_attrs.contains_key("variadic") {
+                panic!("shape cannot be used on a variadic argument");
+            }
+            if async_function && (passthrough_type(&typed_arg.ty).is_some() || is_range_type(&typed_arg.ty)) {
+                panic!("Variant, XlRef and XlRange arguments cannot be sent to another thread, they are not supported in async functions");
+            }
             // Owned type
             let owned_type = {
                 let ty = &typed_arg.ty;
                 match &**ty {
+                    _ if variadic_count(&xl_attrs).is_some() => variadic_conversion(&arg_name, ty, &trace_arg),
+                    _ if is_range_type(ty) => quote!(
+                        if #arg_name.is_missing() {
+                            return Err(Box::new(xladd::variant::XLAddError::MissingArgument(stringify!(#func).to_string(),stringify!(#arg_name).to_string())));
+                        }
+                        #trace_arg
+                    ),
+                    _ if passthrough_type(ty).is_some() => quote!(
+                        #trace_arg
+                    ),
                     syn::Type::Path(p) => {
                         let segment = &p.path.segments[0];
                         let ident = &segment.ident;
@@ -79,7 +771,7 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                                 return Err(Box::new(xladd::variant::XLAddError::MissingArgument(stringify!(#func).to_string(),stringify!(#arg_name).to_string())));
                             }
                             let #arg_name = std::convert::TryInto::<#p_type>::try_into(&#arg_name)?; 
-                            log::trace!("{}:[{:?}]",stringify!(#arg_name),#arg_name);
+                            #trace_arg
                         )
                     }
                     syn::Type::Reference(p) => {
@@ -96,8 +788,9 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                                                 if #arg_name.is_missing_or_null() {
                                                     return Err(Box::new(xladd::variant::XLAddError::MissingArgument(stringify!(#func).to_string(),stringify!(#arg_name).to_string())));
                                                 }
+                                                #shape_check
                                                 let #arg_name = std::convert::TryInto::<Vec<#ident>>::try_into(&#arg_name)?;
-                                                log::trace!("{}:[{:?}]",stringify!(#arg_name),#arg_name);
+                                                #trace_arg
                                                 //let #arg_name = #arg_name.as_slice();
                                         )
                                     }
@@ -111,9 +804,10 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                                                     quote!( if #arg_name.is_missing_or_null() {
                                                                 return Err(Box::new(xladd::variant::XLAddError::MissingArgument(stringify!(#func).to_string(),stringify!(#arg_name).to_string())));
                                                             }
+                                                            #shape_check
                                                             let #arg_name = std::convert::TryInto::<Vec<String>>::try_into(&#arg_name)?;
                                                             let #arg_name = #arg_name.iter().map(AsRef::as_ref).collect::<Vec<_>>();
-                                                            log::trace!("{}:[{:?}]",stringify!(#arg_name),#arg_name);
+                                                            #trace_arg
                                                             //let #arg_name = #arg_name.as_slice();
                                                     )
                                                 } else {
@@ -135,7 +829,7 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                                             return Err(Box::new(xladd::variant::XLAddError::MissingArgument(stringify!(#func).to_string(),stringify!(#arg_name).to_string())));
                                         }
                                         let #arg_name = std::convert::TryInto::<String>::try_into(&#arg_name)?;
-                                        log::trace!("{}:[{:?}]",stringify!(#arg_name),#arg_name);
+                                        #trace_arg
                                        // let #arg_name = #arg_name.as_str();
                                     )
                                 } else { 
@@ -144,7 +838,7 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                                             return Err(Box::new(xladd::variant::XLAddError::MissingArgument(stringify!(#func).to_string(),stringify!(#arg_name).to_string())));
                                         }
                                         let #arg_name = std::convert::TryInto::<#ident>::try_into(&#arg_name)?;
-                                        log::trace!("{}:[{:?}]",stringify!(#arg_name),#arg_name);
+                                        #trace_arg
                                         )
                                     }
                             }
@@ -153,6 +847,16 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
 
                         // or Path
                     }
+                    syn::Type::Array(a) => {
+                        let conversion = fixed_array(func, &arg_name, a);
+                        quote!(
+                            if #arg_name.is_missing_or_null() {
+                                return Err(Box::new(xladd::variant::XLAddError::MissingArgument(stringify!(#func).to_string(),stringify!(#arg_name).to_string())));
+                            }
+                            #conversion
+                            #trace_arg
+                        )
+                    }
                     _ => panic!("Type not covered"),
                 }
             };
@@ -178,6 +882,13 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
             let owned_type = {
                 let ty = &typed_arg.ty;
                 match &**ty {
+                    _ if is_range_type(ty) => quote!(),
+                    _ if passthrough_type(ty).as_deref() == Some("XlRef") => {
+                        quote!( let #arg_name: #ty = From::from(&#arg_name); )
+                    }
+                    syn::Type::Reference(_) if passthrough_type(ty).is_some() => {
+                        quote!( let #arg_name = &#arg_name; )
+                    }
                     syn::Type::Path(_) => {
                         quote!()
                     }
@@ -223,6 +934,7 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
 
                         // or Path
                     }
+                    syn::Type::Array(_) => quote!(),
                     _ => panic!("Type not covered"),
                 }
             };
@@ -232,59 +944,46 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
         FnArg::Receiver(_) => panic!("Free functions only"),
     });
     // Parse the doc comments
-
-    let comments = &item.attrs.iter().filter_map(|attr: &syn::Attribute| {
-        let segment = &attr.path.segments[0];
-        if segment.ident == "doc" {
-            Some(attr.tokens.to_string())
-        } else {
-            None
-        }
-    });
+    let doc_comments = parse_doc_comments(&item.attrs);
+    // Excel matches argument help to arguments by position, so every parameter gets an entry. Undocumented
+    // parameters fall back to "name (type)" and raise a warning
+    let mut undocumented = Vec::new();
+    let mut attr_helps = Vec::new();
+    let mut param_helps = Vec::new();
     let args = typed_args
         .clone()
-        .filter_map(|(name, _)| {
-            let name = name.to_string();
-            comments.clone().find_map(|v| {
-                if v.starts_with(&format!("= \" * {} -", name)) {
-                    let v = &v[name.len() + 9..v.len() - 1];
-                    Some(quote! {#v})
-                } else {
-                    None
+        .zip(item.sig.inputs.iter())
+        .flat_map(|((name, _), arg)| {
+            let (xl_attrs, ty) = match arg {
+                FnArg::Typed(typed_arg) => (xl_param_attrs(&typed_arg.attrs), &*typed_arg.ty),
+                FnArg::Receiver(_) => panic!("xl_func cannot be used on methods"),
+            };
+            let count = variadic_count(&xl_attrs).unwrap_or(1);
+            // #[xl(help = "...")] takes precedence over the doc comment
+            // Raw identifiers such as r#type are documented and shown without the r#
+            let name = name.to_string().trim_start_matches("r#").to_string();
+            let help = match (xl_attrs.get("help"), doc_comments.args.get(&name)) {
+                (Some(help), _) => {
+                    attr_helps.push((help.clone(), arg.span()));
+                    help.clone()
                 }
-            })
+                (None, Some((help, _))) => help.clone(),
+                (None, None) => {
+                    undocumented.push(undocumented_warning(func, &name, arg.span()));
+                    format!("{} ({})", name, type_name(ty))
+                }
+            };
+            param_helps.push(help.clone());
+            // Every slot of a variadic argument gets the same help
+            std::iter::repeat_n(help, count)
         })
         .collect::<Vec<_>>();
-    let ret = comments.clone().find_map(|v| {
-        if v.starts_with("= \" * ret -") {
-            let v = &v[12..v.len() - 1];
-            Some(v.to_owned())
-        } else {
-            None
-        }
-    });
-    let docs = comments.clone().find_map(|v| {
-        if !v.starts_with("= \" *") {
-            let v = &v[4..v.len() - 1];
-            Some(v.to_owned())
-        } else {
-            None
-        }
-    });
-
-    let docs_ret = vec![
-        if ret.is_some() {
-            ret.as_ref().unwrap()
-        } else {
-            ""
-        },
-        if docs.is_some() {
-            docs.as_ref().unwrap()
-        } else {
-            ""
-        },
-    ]
-    .join(" and ");
+    let docs_ret = [doc_comments.ret.as_str(), doc_comments.description.as_str()]
+        .iter()
+        .filter(|s| !s.is_empty())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" and ");
     // Return type convert back to variant
     let output = {
         match output {
@@ -341,6 +1040,9 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
 
                                             }
                                         }
+                                        syn::Type::Path(_) if passthrough_type(path).is_some() => {
+                                            quote! {Ok(res)}
+                                        },
                                         syn::Type::Path(_) => {
                                             quote! {Ok(xladd::variant::Variant::from(res))}
                                         },
@@ -364,42 +1066,131 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
         }
     };
     // Now collate
+    let arg_types = item.sig.inputs.iter().filter_map(|arg| match arg {
+        FnArg::Typed(typed_arg) => Some(&*typed_arg.ty),
+        FnArg::Receiver(_) => None,
+    }).collect::<Vec<_>>();
+    // A variadic argument is registered as `count` separate slots named arg1, arg2, ...
+    let variadic = item.sig.inputs.iter().filter_map(|arg| match arg {
+        FnArg::Typed(typed_arg) => Some(variadic_count(&xl_param_attrs(&typed_arg.attrs))),
+        FnArg::Receiver(_) => None,
+    }).collect::<Vec<_>>();
+    if variadic.iter().rev().skip(1).any(Option::is_some) {
+        panic!("Only the last argument can be variadic");
+    }
+    let variadic_slots = |name: &proc_macro2::TokenStream, count: usize| {
+        (1..=count)
+            .map(|i| proc_macro2::Ident::new(&format!("{}{}", name, i), proc_macro2::Span::call_site()))
+            .collect::<Vec<_>>()
+    };
     let lpx_oper_args = typed_args
         .clone()
-        .map(|(name, _)| quote!(#name: xladd::xlcall::LPXLOPER12))
+        .zip(variadic.iter())
+        .flat_map(|((name, _), count)| match count {
+            Some(count) => variadic_slots(&name, *count).into_iter().map(|slot| quote!(#slot: xladd::xlcall::LPXLOPER12)).collect(),
+            None => vec![quote!(#name: xladd::xlcall::LPXLOPER12)],
+        })
         .collect::<Vec<_>>();
     let variant_args = typed_args
         .clone()
-        .map(|(name, _)| quote!(#name: xladd::variant::Variant))
+        .zip(arg_types.iter().zip(variadic.iter()))
+        .map(|((name, _), (ty, count))| if is_range_type(ty) {
+            quote!(#name: #ty)
+        } else if count.is_some() {
+            quote!(#name: Vec<xladd::variant::Variant>)
+        } else {
+            quote!(#name: xladd::variant::Variant)
+        })
         .collect::<Vec<_>>();
     let to_variant = typed_args
         .clone()
-        .map(|(name, _)| quote!(let #name = xladd::variant::Variant::from(#name);))
+        .zip(arg_types.iter().zip(variadic.iter()))
+        .map(|((name, _), (ty, count))| if is_range_type(ty) {
+            quote!(let #name = unsafe { <#ty>::from_xloper(#name) };)
+        } else if let Some(count) = count {
+            let slots = variadic_slots(&name, *count);
+            quote!(let #name = vec![#(xladd::variant::Variant::from(#slots)),*];)
+        } else {
+            quote!(let #name = xladd::variant::Variant::from(#name);)
+        })
+        .collect::<Vec<_>>();
+    // The same arguments taken from worksheet values, for the <name>_xl function. Omitted trailing
+    // arguments are missing as they would be in Excel
+    let from_values = typed_args
+        .clone()
+        .zip(arg_types.iter().zip(variadic.iter()))
+        .map(|((name, _), (ty, count))| if is_range_type(ty) {
+            quote!(let #name = <#ty>::from_value(__xladd_values.next().map(xladd::variant::Variant::from).unwrap_or_else(xladd::variant::Variant::missing));)
+        } else if let Some(count) = count {
+            quote!(let #name = __xladd_values.by_ref().take(#count).map(xladd::variant::Variant::from).collect::<Vec<_>>();)
+        } else {
+            quote!(let #name = __xladd_values.next().map(xladd::variant::Variant::from).unwrap_or_else(xladd::variant::Variant::missing);)
+        })
         .collect::<Vec<_>>();
     let caller_args = typed_args
         .clone()
         .map(|(name, _)| quote!(#name))
         .collect::<Vec<_>>();
-    let caller_args_str = typed_args
-        .clone()
-        .map(|(name, _)| name.to_string())
+    // The names Excel shows, #[xl(name = "...")] or the Rust name
+    let mut excel_arg_names = Vec::new();
+    for arg in item.sig.inputs.iter() {
+        if let FnArg::Typed(typed_arg) = arg {
+            match xl_param_attrs(&typed_arg.attrs).get("name") {
+                Some(name) if name.trim().is_empty() || name.contains(',') => {
+                    let msg = format!("Argument name \"{}\" must not be empty or contain commas", name);
+                    return syn::Error::new(typed_arg.span(), msg).to_compile_error();
+                }
+                Some(name) => excel_arg_names.push(name.clone()),
+                None => {
+                    let pat = &typed_arg.pat;
+                    excel_arg_names.push(quote!(#pat).to_string().trim_start_matches("r#").to_string())
+                }
+            }
+        }
+    }
+    let caller_args_str = excel_arg_names
+        .iter()
+        .zip(variadic.iter())
+        .flat_map(|(name, count)| match count {
+            Some(count) => (1..=*count).map(|i| format!("{}{}", name, i)).collect(),
+            None => vec![name.clone()],
+        })
         .collect::<Vec<_>>()
         .join(",");
-    let mut q_args = typed_args
-        .clone()
-        .map(|(_, _)| "Q")
+    // References are registered as U so the function can see where the range came from
+    let q_args = arg_types
+        .iter()
+        .zip(variadic.iter())
+        .map(|(ty, count)| if is_range_type(ty) { "U".to_string() } else { "Q".repeat(count.unwrap_or(1)) })
         .collect::<Vec<_>>()
         .join("");
-    // Mark function as async
-    if async_function {
-        q_args.insert(0,'>');
-        q_args.push('X');
-    } else {
-        // Return type is a variant
-        q_args.push('Q');
+    let q_args = match type_text(&q_args, &Modifiers { async_function, thread_safe, volatile, macro_equivalent, cluster_safe }) {
+        Ok(type_text) => type_text,
+        Err(msg) => return syn::Error::new_spanned(&attr, msg).to_compile_error(),
+    };
+    // Excel limits. The async return handle has no help text so it isn't counted
+    if args.len() > MAX_ARGUMENTS {
+        let msg = format!("{} registers {} arguments, Excel allows at most {}", xl_name, args.len(), MAX_ARGUMENTS);
+        return syn::Error::new(item.sig.inputs.span(), msg).to_compile_error();
+    }
+    if caller_args_str.chars().count() > MAX_TEXT_LENGTH {
+        let msg = format!("The argument names of {} are {} characters long, Excel allows at most {}", xl_name, caller_args_str.chars().count(), MAX_TEXT_LENGTH);
+        return syn::Error::new(item.sig.inputs.span(), msg).to_compile_error();
+    }
+    for (help, span) in doc_comments.args.values().chain(attr_helps.iter()) {
+        if help.chars().count() > MAX_TEXT_LENGTH {
+            let msg = format!("Argument help is {} characters long, Excel allows at most {}", help.chars().count(), MAX_TEXT_LENGTH);
+            return syn::Error::new(*span, msg).to_compile_error();
+        }
+    }
+    if docs_ret.chars().count() > MAX_TEXT_LENGTH {
+        let msg = format!("The description of {} is {} characters long, Excel allows at most {}", xl_name, docs_ret.chars().count(), MAX_TEXT_LENGTH);
+        return syn::Error::new(func.span(), msg).to_compile_error();
+    }
+    if category.chars().count() > MAX_TEXT_LENGTH {
+        let msg = format!("The category of {} is longer than {} characters", xl_name, MAX_TEXT_LENGTH);
+        return syn::Error::new(func.span(), msg).to_compile_error();
     }
-    // not_thread_safe
-    if !(single_threaded || async_function) { q_args.push('$'); };
     let convert_to_owned_rust_types = typed_args
         .clone()
         .map(|(_, owned_type)| owned_type)
@@ -410,44 +1201,130 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
         .map(|(_, owned_type)| owned_type)
         .collect::<Vec<_>>();
 
-        let xl_function_str = xl_function.to_string();
+    let xl_function_str = xl_function.to_string();
+    let values_function = proc_macro2::Ident::new(&format!("{}_xl", func), proc_macro2::Span::call_site());
+    // Everything the catalogue needs to describe the function
+    let arg_types = arg_types.iter().map(|ty| type_name(ty)).collect::<Vec<_>>();
+    let return_type = return_type_name(&item.sig.output);
+    let flags = [
+        ("async", async_function),
+        ("volatile", volatile),
+        ("macro_equivalent", macro_equivalent),
+        ("cluster_safe", cluster_safe),
+        ("thread_safe", thread_safe),
+    ]
+    .iter()
+    .filter(|(_, set)| *set)
+    .map(|(flag, _)| *flag)
+    .collect::<Vec<_>>();
+    // Async functions return through xlAsyncReturn so they have no <name>_xl function to call
+    let call = if async_function { quote!(None) } else { quote!(Some(#values_function)) };
+    let registration = quote! {
+        type_text: #q_args,
+        arg_text: #caller_args_str,
+        macro_type: #macro_type,
+        category: #category,
+        shortcut: "",
+        help_topic: #help_topic,
+        help_text: #docs_ret,
+        arg_help: &[#(#args),*],
+    };
+    let registry_entry = registry_entry(&xl_function_str, func, &register_function, registration, quote! {
+        arguments: &[#(xladd_derive_runtime::XlArgument {
+            name: #excel_arg_names,
+            rust_type: #arg_types,
+            help: #param_helps,
+        }),*],
+        return_type: #return_type,
+        flags: &[#(#flags),*],
+        call: #call,
+    });
+    let mut item = item.clone();
+    strip_xl_attrs(&mut item);
+    let sync_panic = panic_arm(on_panic, |value| quote!(Ok(#value)));
+    let async_panic = panic_arm(on_panic, |value| quote! {
+        xladd_derive_runtime::excel12(xladd::xlcall::xlAsyncReturn, &mut [Variant::from(raw_ptr), #value]);
+    });
+    // With trace the conversions and the call run in an Invocation, which records the arguments and how
+    // the call ended. A span field is declared for each argument
+    let trace_fields = item.sig.inputs.iter().filter_map(|arg| match arg {
+        FnArg::Typed(typed_arg) => match &*typed_arg.pat {
+            syn::Pat::Ident(ident) => Some(proc_macro2::Ident::new(ident.ident.to_string().trim_start_matches("r#"), ident.ident.span())),
+            _ => None,
+        },
+        FnArg::Receiver(_) => None,
+    }).collect::<Vec<_>>();
+    let invocation = quote!(xladd_derive_runtime::invocation!(#xl_function_str #(, #trace_fields)*));
+    let catch_panic = |call: proc_macro2::TokenStream| if trace {
+        quote!(__xladd_invocation.catch_panic(#call))
+    } else {
+        quote!(xladd_derive_runtime::catch_panic(stringify!(#xl_function), #call))
+    };
+    let traced = |body: proc_macro2::TokenStream| quote! {
+        __xladd_invocation.run(|| -> Result<xladd::variant::Variant, Box<dyn std::error::Error>> {
+            #body
+        })
+    };
     // Async function
     if async_function {
+        let call = catch_panic(quote!(std::panic::AssertUnwindSafe(|| #func(#(#caller_args),*))));
+        let (share, record_result) = if trace {
+            (quote!(let __xladd_invocation = __xladd_invocation.clone();), quote!(let res = res.map(|result| __xladd_invocation.result(result));))
+        } else {
+            (quote!(), quote!())
+        };
+        let body = quote! {
+            #(#convert_to_owned_rust_types)*;
+            let raw_ptr = xladd::variant::XLOPERPtr(return_handle);
+            #share
+            std::thread::spawn(move ||{
+                #(#convert_to_ref_rust_types)*;
+                // Nothing is looked at after a panic but the message, so the arguments can't be seen half updated
+                let res = #call;
+                #record_result
+                match res {
+                    Ok(Ok(v)) => {
+                        xladd_derive_runtime::excel12(
+                                            xladd::xlcall::xlAsyncReturn,
+                                            &mut [Variant::from(raw_ptr), v]);
+                    }
+                    Ok(Err(e)) => {
+                        log::error!("Error {:?}",e.to_string());
+                        xladd_derive_runtime::excel12(
+                                            xladd::xlcall::xlAsyncReturn,
+                                            &mut [Variant::from(raw_ptr), Variant::from(e.to_string())]);
+                    }
+                    // Excel waits for a result however the function ends
+                    #async_panic
+                }
+            });
+            Ok(Variant::default())
+        };
+        // The invocation is shared with the thread the function runs on
+        let body = if trace {
+            let body = traced(body);
+            quote! {
+                let __xladd_invocation = std::sync::Arc::new(#invocation);
+                #body
+            }
+        } else {
+            body
+        };
         let wrapper = quote! {
              // Error handler
              fn #error_handler_function(#(#variant_args),*, return_handle: xladd::xlcall::LPXLOPER12) -> Result<xladd::variant::Variant, Box<dyn std::error::Error>> {
-                log::trace!("{} called [*ASYNC*] ..waiting for results",stringify!(#xl_function));
-                #(#convert_to_owned_rust_types)*;
-                let raw_ptr = xladd::variant::XLOPERPtr(return_handle);
-                std::thread::spawn(move ||{
-                    #(#convert_to_ref_rust_types)*;
-                    match #func(#(#caller_args),*) {
-                        Ok(v) => {
-                            log::trace!("Results [{:?}]",v);
-                            xladd::entrypoint::excel12(
-                                                xladd::xlcall::xlAsyncReturn,
-                                                &mut [Variant::from(raw_ptr), v]);
-                        }
-                        Err(e) => {
-                            log::error!("Error {:?}",e.to_string());
-                            xladd::entrypoint::excel12(
-                                                xladd::xlcall::xlAsyncReturn,
-                                                &mut [Variant::from(raw_ptr), Variant::from(e.to_string())]);
-                        }
-                    }
-                });
-                Ok(Variant::default())
+                #body
             }
             // Excel function
             #[unsafe(no_mangle)]
-            extern "stdcall" fn #xl_function(#(#lpx_oper_args),* ,return_handle: xladd::xlcall::LPXLOPER12) {
+            extern "system" fn #xl_function(#(#lpx_oper_args),* ,return_handle: xladd::xlcall::LPXLOPER12) {
                 #(#to_variant)*
                 match #error_handler_function(#(#caller_args),*, return_handle) {
                     Ok(_) => (),
                     Err(e) => {
                         log::error!("{}",e.to_string());
                         let raw_ptr = xladd::variant::XLOPERPtr(return_handle);
-                        xladd::entrypoint::excel12(
+                        xladd_derive_runtime::excel12(
                             xladd::xlcall::xlAsyncReturn,
                             &mut [xladd::variant::Variant::from(raw_ptr), xladd::variant::Variant::from(e.to_string())],
                         );
@@ -455,37 +1332,46 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                 }
             }
 
-            pub (crate) fn #register_function(reg: &xladd::registrator::Reg) {
-                reg.add(#xl_function_str,#q_args,#caller_args_str,#category,#docs_ret,&[#(#args),*]);
-            }
+            #registry_entry
+            #(#undocumented)*
             // User function
             #item
         };
-        wrapper.into()
+        wrapper
     } else {
+        let call = catch_panic(quote!(|| #func(#(#caller_args),*)));
+        let result = if trace { quote!(__xladd_invocation.result(result)) } else { quote!(result) };
+        let body = quote! {
+            #(#convert_to_owned_rust_types)*;
+            #(#convert_to_ref_rust_types)*;
+            // A panic is logged in full by catch_panic, the cell gets a short message
+            let res = #call;
+            match res {
+                Ok(result) => {
+                    let res = #result?;
+                    #output
+                }
+                #sync_panic
+            }
+        };
+        let body = if trace {
+            let body = traced(body);
+            quote! {
+                let __xladd_invocation = #invocation;
+                #body
+            }
+        } else {
+            body
+        };
         let wrapper = quote! {
 
             // Error handler
             fn #error_handler_function(#(#variant_args),*) -> Result<xladd::variant::Variant, Box<dyn std::error::Error>> {
-                log::trace!("{} called",stringify!(#xl_function));
-                #(#convert_to_owned_rust_types)*;
-                #(#convert_to_ref_rust_types)*;
-                let res = std::panic::catch_unwind(|| #func(#(#caller_args),*));
-                match res {
-                    Ok(result) => {
-                        let res = result?;
-                        log::trace!("Results [{:?}]",res);
-                        #output        
-                    }
-                    Err(_) => {
-                        log::error!("Unexpected error while calling function"); 
-                        Err("Error when trying to execute function, check for invalid values, ranges, or #n/a".into())
-                    }
-                }
+                #body
             }
             // Excel function
             #[unsafe(no_mangle)]
-            extern "stdcall" fn #xl_function(#(#lpx_oper_args),*)  -> xladd::xlcall::LPXLOPER12 {
+            extern "system" fn #xl_function(#(#lpx_oper_args),*)  -> xladd::xlcall::LPXLOPER12 {
                 #(#to_variant)*
                 match #error_handler_function(#(#caller_args),*) {
                     Ok(v) => xladd::xlcall::LPXLOPER12::from(v),
@@ -496,12 +1382,945 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                 }
             }
 
-            pub (crate) fn #register_function(reg: &xladd::registrator::Reg) {
-                reg.add(#xl_function_str,#q_args,#caller_args_str,#category,#docs_ret,&[#(#args),*]);
+            // Call the function with worksheet values rather than XLOPER12 pointers, going through the same
+            // conversions and errors as the Excel function. Public so integration tests can call it
+            #[allow(dead_code)]
+            pub fn #values_function(values: &[xladd_derive_runtime::XlValue]) -> xladd_derive_runtime::XlValue {
+                let mut __xladd_values = values.iter();
+                #(#from_values)*
+                match #error_handler_function(#(#caller_args),*) {
+                    Ok(v) => xladd_derive_runtime::XlValue::from(&v),
+                    Err(e) => xladd_derive_runtime::XlValue::Text(e.to_string()),
+                }
             }
+
+            #registry_entry
+            #(#undocumented)*
             // User function
             #item
         };
-    wrapper.into()
+    wrapper
     }   
-}
\ No newline at end of file
+}
+
+#[proc_macro_attribute]
+pub fn xl_command(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let mut expanded = expand_xl_command(attr.into(), input.into());
+    expanded.extend(track_manifest());
+    expanded.into()
+}
+
+// The code generation behind #[xl_command]
+fn expand_xl_command(attr: proc_macro2::TokenStream, input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let item = syn::parse2::<ItemFn>(input).expect("Failed to parse.");
+    let params = parse_params(attr);
+    let name = item.sig.ident.to_string();
+    let category = if let Some(v) = params.get("category") { v } else { "" };
+    let prefix = if let Some(v) = params.get("prefix") { v } else { "xl" };
+    let rename = if let Some(v) = params.get("rename") { v } else { name.as_str() };
+    let shortcut = if let Some(v) = params.get("shortcut") { v } else { "" };
+    if !shortcut.is_empty() && (shortcut.len() != 1 || !shortcut.chars().all(|c| c.is_ascii_alphabetic())) {
+        panic!("shortcut must be a single letter, \"r\" for Ctrl+R or \"R\" for Ctrl+Shift+R");
+    }
+    if !item.sig.inputs.is_empty() {
+        panic!("Commands are run from buttons, the ribbon or Application.Run and cannot take arguments");
+    }
+    let func = &item.sig.ident;
+    let xl_name = exported_name(prefix, rename);
+    if let Err(e) = validate_excel_name(&xl_name) {
+        return syn::Error::new(func.span(), e).to_compile_error();
+    }
+    let help_topic = match help_topic(params.get("help_topic").map(String::as_str), &xl_name) {
+        Ok(help_topic) => help_topic,
+        Err(e) => return syn::Error::new(func.span(), e).to_compile_error(),
+    };
+    // Commands have no cell to show a panic in, so only abort changes anything
+    let abort = match on_panic(params.get("on_panic").map(String::as_str)) {
+        Ok(OnPanic::Abort) => quote!(if cfg!(debug_assertions) { std::process::abort(); }),
+        Ok(_) => quote!(),
+        Err(e) => return syn::Error::new(func.span(), e).to_compile_error(),
+    };
+    let xl_function = proc_macro2::Ident::new(
+        &xl_name,
+        proc_macro2::Span::call_site(),
+    );
+    let register_function = proc_macro2::Ident::new(
+        &format!("register_{}", func),
+        proc_macro2::Span::call_site(),
+    );
+    let xl_function_str = xl_function.to_string();
+    let docs = parse_doc_comments(&item.attrs).description;
+    let registration = quote! {
+        type_text: "J",
+        arg_text: "",
+        macro_type: xladd_derive_runtime::MacroType::Command,
+        category: #category,
+        shortcut: #shortcut,
+        help_topic: #help_topic,
+        help_text: #docs,
+        arg_help: &[],
+    };
+    let registry_entry = registry_entry(&xl_function_str, func, &register_function, registration, quote! {
+        arguments: &[],
+        return_type: "",
+        flags: &[],
+        call: None,
+    });
+    let call = if params.get("trace").map(|v| v != "false").unwrap_or(true) {
+        quote! {
+            let __xladd_invocation = xladd_derive_runtime::invocation!(#xl_function_str);
+            let res = __xladd_invocation.catch_panic(#func).map(|result| __xladd_invocation.result(result));
+        }
+    } else {
+        quote!(let res = xladd_derive_runtime::catch_panic(stringify!(#xl_function), #func);)
+    };
+    let wrapper = quote! {
+        // Excel command, returns 1 on success
+        #[unsafe(no_mangle)]
+        extern "system" fn #xl_function() -> i32 {
+            #call
+            match res {
+                Ok(Ok(_)) => 1,
+                Ok(Err(e)) => {
+                    log::error!("{}",e.to_string());
+                    0
+                }
+                // Already logged by catch_panic
+                Err(_) => {
+                    #abort
+                    0
+                }
+            }
+        }
+
+        #registry_entry
+        // User function
+        #item
+    };
+    wrapper
+}
+
+// Add the module defaults to the #[xl_func] and #[xl_command] attributes that don't set them. Nested
+// inline modules are included unless they have their own #[xl_module]
+fn apply_module_defaults(items: &mut [syn::Item], defaults: &BTreeMap<String, String>) {
+    let is_attr = |attr: &syn::Attribute, names: &[&str]| {
+        attr.path.segments.last().map(|s| names.iter().any(|name| s.ident == name)).unwrap_or(false)
+    };
+    for item in items.iter_mut() {
+        match item {
+            syn::Item::Fn(f) => {
+                for attr in f.attrs.iter_mut().filter(|attr| is_attr(attr, &["xl_func", "xl_command"])) {
+                    let mut args = match attr.tokens.clone().into_iter().next() {
+                        Some(proc_macro2::TokenTree::Group(group)) => group.stream().into_iter().collect::<Vec<_>>(),
+                        _ => Vec::new(),
+                    };
+                    if matches!(args.last(), Some(proc_macro2::TokenTree::Punct(p)) if p.as_char() == ',') {
+                        args.pop();
+                    }
+                    let existing = parse_params(args.iter().cloned().collect());
+                    let mut args = if args.is_empty() { Vec::new() } else { vec![quote!(#(#args)*)] };
+                    for (key, value) in defaults.iter().filter(|(key, _)| !existing.contains_key(*key)) {
+                        let key = proc_macro2::Ident::new(key, proc_macro2::Span::call_site());
+                        args.push(if value.is_empty() { quote!(#key) } else { quote!(#key = #value) });
+                    }
+                    attr.tokens = quote!((#(#args),*));
+                }
+            }
+            syn::Item::Mod(m) if !m.attrs.iter().any(|attr| is_attr(attr, &["xl_module"])) => {
+                if let Some((_, items)) = m.content.as_mut() {
+                    apply_module_defaults(items, defaults);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// Defaults such as prefix and category for every #[xl_func] and #[xl_command] in an inline module
+#[proc_macro_attribute]
+pub fn xl_module(attr: TokenStream, input: TokenStream) -> TokenStream {
+    expand_xl_module(attr.into(), input.into()).into()
+}
+
+// The code generation behind #[xl_module]
+fn expand_xl_module(attr: proc_macro2::TokenStream, input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let defaults = parse_params(attr);
+    let mut module = syn::parse2::<syn::ItemMod>(input).expect("Failed to parse.");
+    match module.content.as_mut() {
+        Some((_, items)) => apply_module_defaults(items, &defaults),
+        None => {
+            let msg = "xl_module can only be used on an inline module, mod name { ... }";
+            return syn::Error::new(module.ident.span(), msg).to_compile_error();
+        }
+    }
+    quote!(#module)
+}
+
+// Generates an xlAutoOpen that registers every #[xl_func] and #[xl_command] in the add-in
+#[proc_macro]
+pub fn xl_auto_open(_input: TokenStream) -> TokenStream {
+    let auto_open = quote! {
+        #[unsafe(no_mangle)]
+        pub extern "system" fn xlAutoOpen() -> i32 {
+            if xladd_derive_runtime::register_all() { 1 } else { 0 }
+        }
+    };
+    auto_open.into()
+}
+
+// Generates xlAutoClose and xlAutoRemove which unregister everything registered by the register_* functions,
+// otherwise Excel keeps the functions and crashes calling into the unloaded dll when they are recalculated
+#[proc_macro]
+pub fn xl_auto_close(_input: TokenStream) -> TokenStream {
+    expand_xl_auto_close().into()
+}
+
+fn expand_xl_auto_close() -> proc_macro2::TokenStream {
+    quote! {
+        #[unsafe(no_mangle)]
+        pub extern "system" fn xlAutoClose() -> i32 {
+            xladd_derive_runtime::unregister_all();
+            1
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "system" fn xlAutoRemove() -> i32 {
+            xladd_derive_runtime::unregister_all();
+            1
+        }
+    }
+}
+
+// key = value pairs where the value can be any expression, e.g. version = env!("CARGO_PKG_VERSION")
+struct AddinArg {
+    key: syn::Ident,
+    value: syn::Expr,
+}
+
+impl syn::parse::Parse for AddinArg {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let key = input.parse()?;
+        input.parse::<syn::Token![=]>()?;
+        let value = input.parse()?;
+        Ok(AddinArg { key, value })
+    }
+}
+
+// Generates xlAddInManagerInfo12 so the add-in shows with a friendly name in the Add-ins dialog, records the
+// name, version and author, and adds a <PREFIX>_VERSION(field) function that returns them
+#[proc_macro]
+pub fn xl_addin(input: TokenStream) -> TokenStream {
+    expand_xl_addin(input.into()).into()
+}
+
+// The code generation behind xl_addin!
+fn expand_xl_addin(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    use syn::parse::Parser;
+    let parser = syn::punctuated::Punctuated::<AddinArg, syn::Token![,]>::parse_terminated;
+    let args = match parser.parse2(input) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error(),
+    };
+    let mut values = BTreeMap::new();
+    for arg in args.iter() {
+        match arg.key.to_string().as_str() {
+            "name" | "version" | "author" | "prefix" | "category" => {
+                values.insert(arg.key.to_string(), &arg.value);
+            }
+            key => {
+                let msg = format!("Unknown xl_addin argument {}, expected name, version, author, prefix or category", key);
+                return syn::Error::new(arg.key.span(), msg).to_compile_error();
+            }
+        }
+    }
+    // prefix and category end up in an attribute so they have to be literals
+    let literal = |key: &str, default: &str| match values.get(key) {
+        Some(syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(s), .. })) => Ok(s.value()),
+        Some(expr) => Err(syn::Error::new(expr.span(), format!("{} must be a string literal", key))),
+        None => Ok(default.to_string()),
+    };
+    let (prefix, category) = match (literal("prefix", "xl"), literal("category", "")) {
+        (Ok(prefix), Ok(category)) => (prefix, category),
+        (Err(e), _) | (_, Err(e)) => return e.to_compile_error(),
+    };
+    let name = match values.get("name") {
+        Some(name) => name,
+        None => return syn::Error::new(proc_macro2::Span::call_site(), "xl_addin requires a name").to_compile_error(),
+    };
+    let version = values.get("version").map(|v| quote!(#v)).unwrap_or(quote!(""));
+    let author = values.get("author").map(|v| quote!(#v)).unwrap_or(quote!(""));
+    quote! {
+        xladd_derive_runtime::inventory::submit! {
+            xladd_derive_runtime::AddIn { name: #name, version: #version, author: #author }
+        }
+
+        // Excel asks for the add-in name with action 1
+        #[unsafe(no_mangle)]
+        pub extern "system" fn xlAddInManagerInfo12(action: xladd::xlcall::LPXLOPER12) -> xladd::xlcall::LPXLOPER12 {
+            let action = xladd_derive_runtime::excel12(
+                xladd::xlcall::xlCoerce,
+                &mut [xladd::variant::Variant::from(action), xladd::variant::Variant::from(xladd::xlcall::xltypeNum as f64)],
+            );
+            match std::convert::TryInto::<f64>::try_into(&action) {
+                Ok(action) if action == 1.0 => xladd::xlcall::LPXLOPER12::from(xladd::variant::Variant::from(#name)),
+                _ => xladd::xlcall::LPXLOPER12::from(xladd::variant::Variant::from_err(xladd::xlcall::xlerrValue)),
+            }
+        }
+
+        /// Name, version or author of the add-in
+        /// * field - One of "name", "version" or "author". The version if omitted
+        #[xladd_derive::xl_func(prefix = #prefix, rename = "VERSION", category = #category)]
+        fn __xladd_addin_version(field: xladd::variant::Variant) -> Result<String, Box<dyn std::error::Error>> {
+            let addin = xladd_derive_runtime::addin().ok_or("No add-in metadata")?;
+            if field.is_missing_or_null() {
+                return Ok(addin.version.to_string());
+            }
+            match std::convert::TryInto::<String>::try_into(&field)?.to_ascii_lowercase().as_str() {
+                "name" => Ok(addin.name.to_string()),
+                "version" => Ok(addin.version.to_string()),
+                "author" => Ok(addin.author.to_string()),
+                field => Err(format!("Unknown field {}, expected name, version or author", field).into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excel_names() {
+        assert!(validate_excel_name("xl_add").is_ok());
+        assert!(validate_excel_name("my-func").unwrap_err().contains("contains '-'"));
+        assert!(validate_excel_name("1func").is_err());
+        assert!(validate_excel_name(&"a".repeat(256)).unwrap_err().contains("longer than 255"));
+    }
+
+    #[test]
+    fn excel_names_from_attributes() {
+        let item = quote! {
+            fn add(a: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(a) }
+        };
+        assert!(expand_xl_func(quote!(rename = "my-add"), item.clone()).to_string().contains("Excel function name xl_my-add contains '-'"));
+        assert!(expand_xl_func(quote!(prefix = "1x"), item.clone()).to_string().contains("cannot start with a digit"));
+        // The prefix is always followed by _, so a rename that looks like a cell reference is still a valid name
+        let expanded = expand_xl_func(quote!(rename = "ABC1"), item).to_string();
+        assert!(expanded.contains("fn xl_ABC1") && !expanded.contains("compile_error"));
+    }
+
+    #[test]
+    fn argument_limit() {
+        let expand = |count: usize| {
+            let count = proc_macro2::Literal::usize_unsuffixed(count);
+            let item = quote! {
+                fn total(#[xl(variadic, count = #count)] values: &[f64]) -> Result<f64, Box<dyn std::error::Error>> {
+                    Ok(values.iter().sum())
+                }
+            };
+            expand_xl_func(quote!(), item).to_string()
+        };
+        // 245 slots of names are too long for the argument names anyway, but that is a separate check
+        assert!(!expand(245).contains("Excel allows at most 245"));
+        assert!(expand(246).contains("xl_total registers 246 arguments, Excel allows at most 245"));
+    }
+
+    fn doc_attrs(lines: &[&str]) -> Vec<syn::Attribute> {
+        lines.iter().map(|line| syn::parse_quote!(#[doc = #line])).collect()
+    }
+
+    #[test]
+    fn doc_comments_description() {
+        let docs = parse_doc_comments(&doc_attrs(&[
+            " Prices a *European* option using",
+            " the `Black-Scholes` formula, see [wiki](https://en.wikipedia.org/wiki/Black-Scholes_model)",
+            "",
+            " Second **paragraph** for spot_price and 2 * 3",
+        ]));
+        assert_eq!(docs.description, "Prices a European option using the Black-Scholes formula, see wiki Second paragraph for spot_price and 2 * 3");
+        assert_eq!(docs.ret, "");
+    }
+
+    #[test]
+    fn doc_comments_arguments() {
+        let docs = parse_doc_comments(&doc_attrs(&[
+            " Description",
+            " * spot - Spot price",
+            " * `strike`: Strike of the option",
+            "   continued on the next line",
+            " - vol - Implied _volatility_",
+            " - rate: Rate",
+            " * ret - returns the price",
+        ]));
+        let help = |name: &str| docs.args.get(name).map(|(help, _)| help.clone());
+        assert_eq!(docs.description, "Description");
+        assert_eq!(help("spot").as_deref(), Some("Spot price"));
+        assert_eq!(help("strike").as_deref(), Some("Strike of the option continued on the next line"));
+        assert_eq!(help("vol").as_deref(), Some("Implied volatility"));
+        assert_eq!(help("rate").as_deref(), Some("Rate"));
+        assert_eq!(docs.ret, "returns the price");
+    }
+
+    #[test]
+    fn doc_comments_raw_strings() {
+        let attrs: Vec<syn::Attribute> = vec![syn::parse_quote!(#[doc = r#" * x -   The "x" value  "#])];
+        let docs = parse_doc_comments(&attrs);
+        assert_eq!(docs.args.get("x").map(|(help, _)| help.as_str()), Some(r#"The "x" value"#));
+    }
+
+    #[test]
+    fn argument_help_by_position() {
+        let item = quote! {
+            /// Prices an option
+            /// * strike - Strike of the option
+            fn price(
+                spot: f64,
+                strike: f64,
+                #[xl(help = "Call or put")] kind: &str,
+                #[xl(variadic, count = 2)] bumps: &[f64],
+            ) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(spot)
+            }
+        };
+        let expanded = expand_xl_func(quote!(), item).to_string();
+        // One entry for every parameter in order, undocumented ones fall back to name and type
+        assert!(expanded.contains(r#"arg_help : & ["spot (f64)" , "Strike of the option" , "Call or put" , "bumps (&[f64])" , "bumps (&[f64])"]"#));
+    }
+
+    #[test]
+    fn undocumented_arguments() {
+        let item = quote! {
+            /// Prices an option
+            /// * spot - Spot price
+            fn price(spot: f64, vol: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(spot * vol) }
+        };
+        let expanded = expand_xl_func(quote!(), item).to_string();
+        assert!(expanded.contains("argument `vol` of `price` has no `* vol - ...` line in the doc comment"));
+        assert!(!expanded.contains("argument `spot`"));
+        // The markers of price(spot_vol) and price_spot(vol) have the same name, which must not clash
+        let names = |item: proc_macro2::TokenStream| {
+            syn2::parse_file(&expand_xl_func(quote!(), item).to_string())
+                .expect("Generated code does not parse")
+                .items
+                .into_iter()
+                .filter_map(|item| match item {
+                    syn2::Item::Const(c) => Some(c.ident.to_string()),
+                    syn2::Item::Static(s) => Some(s.ident.to_string()),
+                    syn2::Item::Fn(f) => Some(f.sig.ident.to_string()),
+                    _ => None,
+                })
+                .filter(|name| name != "_")
+                .collect::<Vec<_>>()
+        };
+        let mut items = names(quote!(fn price(spot_vol: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(spot_vol) }));
+        items.extend(names(quote!(fn price_spot(vol: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(vol) })));
+        let unique = items.iter().collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(unique.len(), items.len(), "{:?}", items);
+    }
+
+    #[test]
+    fn help_topics() {
+        assert_eq!(validate_help_topic("https://intranet/docs/#xl_price"), Ok("https://intranet/docs/#xl_price!0".to_string()));
+        assert_eq!(validate_help_topic("http://intranet/docs/xl_price!12"), Ok("http://intranet/docs/xl_price!12".to_string()));
+        assert_eq!(validate_help_topic("pricing.chm!100"), Ok("pricing.chm!100".to_string()));
+        assert!(validate_help_topic("pricing.chm").is_err());
+        assert!(validate_help_topic("pricing.chm!abc").is_err());
+        assert!(validate_help_topic("https:///docs").is_err());
+        assert!(validate_help_topic("intranet/docs").is_err());
+        assert!(validate_help_topic("https://intranet/{topic}").is_err());
+        assert!(validate_help_topic("https://intranet/my docs").is_err());
+    }
+
+    #[test]
+    fn manifest_settings() {
+        // Tests run with CARGO_MANIFEST_DIR set to this crate, which has no xladd settings
+        assert_eq!(crate_settings(), BTreeMap::new());
+        assert_eq!(SETTINGS.lock().unwrap().len(), 1);
+        assert_eq!(crate_settings(), BTreeMap::new());
+        assert_eq!(SETTINGS.lock().unwrap().len(), 1);
+        let tracked = track_manifest().to_string();
+        assert!(tracked.starts_with("const _ : & [u8] = include_bytes !") && tracked.contains("Cargo.toml"));
+    }
+
+    #[test]
+    fn type_names() {
+        let ty: syn::Type = syn::parse_quote!(&[&str]);
+        assert_eq!(type_name(&ty), "&[&str]");
+        let output: syn::ReturnType = syn::parse_quote!(-> Result<Vec<f64>, Box<dyn std::error::Error>>);
+        assert_eq!(return_type_name(&output), "Vec<f64>");
+        let output: syn::ReturnType = syn::parse_quote!(-> Box<dyn std::error::Error>);
+        assert_eq!(return_type_name(&output), "Box<dyn std::error::Error>");
+    }
+
+    #[test]
+    fn variadic_count_literals() {
+        let attrs: Vec<syn::Attribute> = vec![syn::parse_quote!(#[xl(variadic, count = 3)])];
+        assert_eq!(variadic_count(&xl_param_attrs(&attrs)), Some(3));
+        let attrs: Vec<syn::Attribute> = vec![syn::parse_quote!(#[xl(variadic, count = "3")])];
+        assert_eq!(variadic_count(&xl_param_attrs(&attrs)), Some(3));
+        let attrs: Vec<syn::Attribute> = vec![syn::parse_quote!(#[xl(variadic)])];
+        assert_eq!(variadic_count(&xl_param_attrs(&attrs)), Some(30));
+    }
+
+    #[test]
+    #[should_panic(expected = "shape cannot be used on a variadic argument")]
+    fn variadic_shape() {
+        let item = quote! {
+            fn total(#[xl(variadic, shape = "column")] values: &[f64]) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(values.iter().sum())
+            }
+        };
+        expand_xl_func(quote!(), item);
+    }
+
+    #[test]
+    fn type_text_sync() {
+        assert_eq!(type_text("QQ", &Modifiers::default()).as_deref(), Ok("QQQ"));
+    }
+
+    #[test]
+    fn type_text_async() {
+        let modifiers = Modifiers { async_function: true, ..Default::default() };
+        assert_eq!(type_text("QU", &modifiers).as_deref(), Ok(">QUX"));
+    }
+
+    #[test]
+    fn type_text_volatile() {
+        let modifiers = Modifiers { volatile: true, ..Default::default() };
+        assert_eq!(type_text("Q", &modifiers).as_deref(), Ok("QQ!"));
+        let modifiers = Modifiers { volatile: true, thread_safe: true, ..Default::default() };
+        assert_eq!(type_text("", &modifiers).as_deref(), Ok("Q!$"));
+    }
+
+    #[test]
+    fn type_text_volatile_async() {
+        let modifiers = Modifiers { volatile: true, async_function: true, ..Default::default() };
+        assert!(type_text("Q", &modifiers).unwrap_err().contains("volatile cannot be combined with async"));
+    }
+
+    #[test]
+    fn type_text_macro_equivalent_and_cluster_safe() {
+        let modifiers = Modifiers { macro_equivalent: true, ..Default::default() };
+        assert_eq!(type_text("U", &modifiers).as_deref(), Ok("QU#"));
+        let modifiers = Modifiers { cluster_safe: true, thread_safe: true, ..Default::default() };
+        assert_eq!(type_text("QQ", &modifiers).as_deref(), Ok("QQQ&$"));
+        let modifiers = Modifiers { volatile: true, macro_equivalent: true, ..Default::default() };
+        assert_eq!(type_text("Q", &modifiers).as_deref(), Ok("QQ!#"));
+    }
+
+    #[test]
+    fn type_text_macro_equivalent_thread_safe() {
+        let modifiers = Modifiers { macro_equivalent: true, thread_safe: true, ..Default::default() };
+        assert!(type_text("Q", &modifiers).unwrap_err().contains("Excel rejects # combined with $"));
+    }
+
+    #[test]
+    fn type_text_macro_equivalent_cluster_safe() {
+        let modifiers = Modifiers { macro_equivalent: true, cluster_safe: true, ..Default::default() };
+        assert!(type_text("Q", &modifiers).unwrap_err().contains("cannot be cluster_safe"));
+    }
+
+    #[test]
+    fn type_text_cluster_safe_reference() {
+        let modifiers = Modifiers { cluster_safe: true, ..Default::default() };
+        assert!(type_text("QU", &modifiers).unwrap_err().contains("cluster_safe functions cannot take reference arguments"));
+    }
+
+    #[test]
+    fn thread_safe_flag() {
+        let item = quote! {
+            /// Adds one
+            /// * x - number
+            fn inc(x: f64) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(x + 1.0)
+            }
+        };
+        assert!(expand_xl_func(quote!(thread_safe), item.clone()).to_string().contains(r#"type_text : "QQ$""#));
+        assert!(expand_xl_func(quote!(), item.clone()).to_string().contains(r#"type_text : "QQ""#));
+        // Async functions are never registered thread safe
+        assert!(expand_xl_func(quote!(async, thread_safe), item).to_string().contains(r#"type_text : ">QX""#));
+    }
+
+    #[test]
+    fn rejected_modifiers() {
+        let item = quote! {
+            /// Adds one
+            /// * x - number
+            fn inc(x: f64) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(x + 1.0)
+            }
+        };
+        let expanded = expand_xl_func(quote!(macro_equivalent, thread_safe), item.clone()).to_string();
+        assert!(expanded.starts_with("compile_error !"), "{}", expanded);
+        assert!(expanded.contains("Excel rejects # combined with $"));
+        let expanded = expand_xl_func(quote!(volatile, async), item).to_string();
+        assert!(expanded.starts_with("compile_error !"), "{}", expanded);
+        assert!(expanded.contains("volatile cannot be combined with async"));
+    }
+
+    // Format the generated code so the snapshots can be reviewed. Update them with `cargo insta review`
+    // or INSTA_UPDATE=always cargo test
+    fn pretty(tokens: proc_macro2::TokenStream) -> String {
+        // syn 1 doesn't know #[unsafe(...)] attributes, the formatter uses syn 2
+        let file = syn2::parse_file(&tokens.to_string()).expect("Generated code does not parse");
+        prettyplease::unparse(&file)
+    }
+
+    #[test]
+    fn expand_sync_function() {
+        let item = quote! {
+            /// Adds two numbers
+            /// * a - first number
+            /// * b - second number
+            /// * ret - the sum
+            fn add(a: f64, b: f64) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(a + b)
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(category = "Maths"), item)));
+    }
+
+    #[test]
+    fn expand_async_function() {
+        let item = quote! {
+            /// Slow lookup
+            /// * key - what to look up
+            fn lookup(key: &str) -> Result<Variant, Box<dyn std::error::Error>> {
+                Ok(Variant::from(key))
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(async), item)));
+    }
+
+    #[test]
+    fn expand_argument_kinds() {
+        let item = quote! {
+            /// Sums a range
+            /// * values - the values
+            fn total(
+                #[xl(name = "Values", shape = "column")] values: &[f64],
+                range: XlRange,
+                raw: Variant,
+                #[xl(variadic, count = "3")] rest: &[f64],
+            ) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(values.iter().sum())
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(prefix = "qa", volatile, hidden, help_topic = "pricing.chm!10"), item)));
+    }
+
+    #[test]
+    fn expand_fixed_arrays() {
+        let item = quote! {
+            /// Weighted determinant
+            /// * m - 2x2 matrix
+            /// * w - weights
+            /// * names - labels
+            fn det(m: [[f64; 2]; 2], w: [f64; 3], names: [String; 2]) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(m[0][0] * m[1][1] - m[0][1] * m[1][0])
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(), item)));
+    }
+
+    #[test]
+    fn expand_xlref_argument() {
+        let item = quote! {
+            /// Describes a cell
+            /// * v - the cell
+            /// * raw - the same cell
+            fn describe(v: XlRef<'_>, raw: &Variant) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(v.as_f64().unwrap_or_default())
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(), item)));
+    }
+
+    #[test]
+    fn expand_text_variadic() {
+        let item = quote! {
+            /// Joins text
+            /// * sep - separator
+            /// * parts - text to join
+            fn join(sep: &str, #[xl(variadic, count = 3)] parts: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+                Ok(parts.join(sep))
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(), item)));
+    }
+
+    #[test]
+    fn expand_bool_return() {
+        let item = quote! {
+            /// Is the number positive
+            /// * x - the number
+            fn positive(x: f64) -> Result<bool, Box<dyn std::error::Error>> {
+                Ok(x > 0.0)
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(), item)));
+    }
+
+    #[test]
+    fn expand_array_return() {
+        let item = quote! {
+            /// Scales the values
+            /// * values - the values
+            /// * k - the scale
+            fn scale(values: &[f64], k: f64) -> Result<(Vec<f64>, usize), Box<dyn std::error::Error>> {
+                Ok((values.iter().map(|v| v * k).collect(), 1))
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(), item)));
+    }
+
+    #[test]
+    fn expand_text_array_return() {
+        let item = quote! {
+            /// Splits text
+            /// * text - the text to split
+            fn split(text: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+                Ok(text.split(',').map(String::from).collect())
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(), item)));
+    }
+
+    #[test]
+    fn expand_variant_return() {
+        let item = quote! {
+            /// Hands the value back
+            /// * v - any value
+            fn identity(v: Variant) -> Result<Variant, Box<dyn std::error::Error>> {
+                Ok(v)
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(), item)));
+    }
+
+    #[test]
+    fn expand_macro_equivalent() {
+        let item = quote! {
+            /// Sheet of a range
+            /// * r - the range
+            fn sheet_of(r: XlRange) -> Result<String, Box<dyn std::error::Error>> {
+                Ok(r.sheet().unwrap_or_default().to_string())
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(macro_equivalent), item)));
+    }
+
+    #[test]
+    fn expand_cluster_safe() {
+        let item = quote! {
+            /// Adds two numbers
+            /// * a - first number
+            /// * b - second number
+            fn cluster_add(a: f64, b: f64) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(a + b)
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(cluster_safe), item)));
+    }
+
+    #[test]
+    fn expand_argument_help() {
+        let item = quote! {
+            /// Prices an option
+            /// * spot_price - overridden by the attribute
+            fn price(
+                #[xl(name = "Spot", help = "Underlying spot price")] spot_price: f64,
+                #[xl(help = "Call or put")] r#type: &str,
+            ) -> Result<f64, Box<dyn std::error::Error>> {
+                Ok(spot_price)
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_func(quote!(), item)));
+    }
+
+    #[test]
+    fn expand_module() {
+        let module = quote! {
+            mod rates {
+                use xladd_derive::xl_func;
+                /// Discount factor
+                #[xl_func]
+                fn df(r: f64, t: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok((-r * t).exp()) }
+                #[xl_func(category = "Curves", prefix = "zz")]
+                fn fwd(r: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(r) }
+                #[xladd_derive::xl_command]
+                fn rebuild() -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+                mod inner {
+                    #[xl_func(rename = "spot")]
+                    fn spot_inner(r: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(r) }
+                }
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_module(quote!(prefix = "qa", category = "Rates"), module)));
+    }
+
+    #[test]
+    fn on_panic_strategies() {
+        let expand = |on_panic: &str| {
+            let item = quote! {
+                fn add(a: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(a) }
+            };
+            expand_xl_func(quote!(on_panic = #on_panic), item).to_string()
+        };
+        assert!(expand("message").contains("Variant :: from (panic . to_string ())"));
+        assert!(expand("error").contains("xlerrNum"));
+        assert!(expand("abort").contains("std :: process :: abort ()"));
+        assert!(expand("ignore").contains("Invalid on_panic"));
+        assert_eq!(on_panic(None), Ok(OnPanic::Message));
+    }
+
+    #[test]
+    fn trace_false() {
+        assert_eq!(parse_params(quote!(category = "Maths", trace = false, volatile)).get("trace").map(String::as_str), Some("false"));
+        assert!(!parse_params(quote!(trace = false)).contains_key("false"));
+        let item = quote! {
+            fn add(a: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(a) }
+        };
+        let traced = expand_xl_func(quote!(), item.clone()).to_string();
+        assert!(traced.contains("invocation ! (\"xl_add\" , a)"));
+        let untraced = expand_xl_func(quote!(trace = false), item).to_string();
+        assert!(!untraced.contains("invocation") && !untraced.contains("trace !"));
+    }
+
+    // The xl_func attribute arguments of each function in a module expanded by xl_module
+    fn module_params(module: proc_macro2::TokenStream) -> BTreeMap<String, BTreeMap<String, String>> {
+        let module = syn::parse2::<syn::ItemMod>(module).expect("Expanded module does not parse");
+        let mut params = BTreeMap::new();
+        let mut items = module.content.map(|(_, items)| items).unwrap_or_default();
+        while let Some(item) = items.pop() {
+            match item {
+                syn::Item::Fn(f) => {
+                    let attr = f.attrs.iter().find(|attr| attr.path.segments.last().unwrap().ident == "xl_func").unwrap();
+                    let args = match attr.tokens.clone().into_iter().next() {
+                        Some(TokenTree::Group(group)) => group.stream(),
+                        _ => proc_macro2::TokenStream::new(),
+                    };
+                    params.insert(f.sig.ident.to_string(), parse_params(args));
+                }
+                syn::Item::Mod(m) => items.extend(m.content.map(|(_, items)| items).unwrap_or_default()),
+                _ => (),
+            }
+        }
+        params
+    }
+
+    #[test]
+    fn module_defaults() {
+        let module = quote! {
+            mod rates {
+                #[xl_func]
+                fn df(r: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(r) }
+                #[xladd_derive::xl_func(volatile)]
+                fn now() -> Result<f64, Box<dyn std::error::Error>> { Ok(0.0) }
+                mod inner {
+                    #[xl_func()]
+                    fn spot(r: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(r) }
+                }
+            }
+        };
+        let params = module_params(expand_xl_module(quote!(prefix = "qa", category = "Rates"), module));
+        for name in ["df", "now", "spot"] {
+            assert_eq!(params[name].get("prefix").map(String::as_str), Some("qa"), "{}", name);
+            assert_eq!(params[name].get("category").map(String::as_str), Some("Rates"), "{}", name);
+        }
+        assert!(params["now"].contains_key("volatile"));
+    }
+
+    #[test]
+    fn module_defaults_overridden() {
+        let module = quote! {
+            mod rates {
+                #[xl_func(category = "Other", prefix = "zz",)]
+                fn fwd(r: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(r) }
+                #[xl_module(category = "Inner")]
+                mod inner {
+                    #[xl_func()]
+                    fn spot(r: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(r) }
+                }
+            }
+        };
+        let params = module_params(expand_xl_module(quote!(prefix = "qa", category = "Rates", trace = false), module));
+        assert_eq!(params["fwd"].get("prefix").map(String::as_str), Some("zz"));
+        assert_eq!(params["fwd"].get("category").map(String::as_str), Some("Other"));
+        assert_eq!(params["fwd"].get("trace").map(String::as_str), Some("false"));
+        // Left to the nested xl_module
+        assert!(params["spot"].is_empty());
+    }
+
+    #[test]
+    fn module_defaults_escaped() {
+        let module = quote! {
+            mod rates {
+                #[xl_func]
+                fn df(r: f64) -> Result<f64, Box<dyn std::error::Error>> { Ok(r) }
+            }
+        };
+        let params = module_params(expand_xl_module(quote!(category = "Rates \"A\" \\ B"), module));
+        assert_eq!(params["df"].get("category").map(String::as_str), Some(r#"Rates "A" \ B"#));
+        assert_eq!(parse_params(quote!(category = r"C:\docs")).get("category").map(String::as_str), Some(r"C:\docs"));
+    }
+
+    #[test]
+    fn expand_auto_close() {
+        insta::assert_snapshot!(pretty(expand_xl_auto_close()));
+    }
+
+    #[test]
+    fn expand_addin() {
+        let input = quote!(name = "Rates Toolkit", version = env!("CARGO_PKG_VERSION"), author = "QA", prefix = "RT", category = "Rates");
+        insta::assert_snapshot!(pretty(expand_xl_addin(input)));
+    }
+
+    #[test]
+    fn expand_addin_version_function() {
+        let addin = expand_xl_addin(quote!(name = "Rates Toolkit", prefix = "RT", category = "Rates"));
+        // syn 1 doesn't know the #[unsafe(...)] attributes of the other items
+        let file = syn2::parse_file(&addin.to_string()).expect("Generated code does not parse");
+        let version = file
+            .items
+            .iter()
+            .find_map(|item| match item {
+                syn2::Item::Fn(f) if f.sig.ident == "__xladd_addin_version" => Some(quote!(#f)),
+                _ => None,
+            })
+            .expect("No version function");
+        let mut version = syn::parse2::<ItemFn>(version).unwrap();
+        // The function goes through xl_func like any other
+        let attr = version.attrs.iter().position(|attr| attr.path.segments.last().unwrap().ident == "xl_func").unwrap();
+        let attr = version.attrs.remove(attr);
+        let args = match attr.tokens.into_iter().next() {
+            Some(TokenTree::Group(group)) => group.stream(),
+            _ => panic!("xl_func has no arguments"),
+        };
+        let expanded = expand_xl_func(args, quote!(#version)).to_string();
+        assert!(expanded.contains("fn RT_VERSION"));
+        assert!(expanded.contains("name : \"RT_VERSION\""));
+        assert!(expanded.contains("category : \"Rates\""));
+        assert!(expanded.contains("help_text : \"Name, version or author of the add-in\""));
+        assert!(expanded.contains("\"One of \\\"name\\\", \\\"version\\\" or \\\"author\\\". The version if omitted\""));
+        assert!(!expanded.contains("compile_error"));
+    }
+
+    #[test]
+    fn addin_errors() {
+        assert!(expand_xl_addin(quote!(version = "1.0")).to_string().contains("xl_addin requires a name"));
+        assert!(expand_xl_addin(quote!(name = "Rates", owner = "QA")).to_string().contains("Unknown xl_addin argument owner"));
+        assert!(expand_xl_addin(quote!(name = "Rates", prefix = env!("PREFIX"))).to_string().contains("prefix must be a string literal"));
+    }
+
+    #[test]
+    fn expand_command() {
+        let item = quote! {
+            /// Recalculates the book
+            fn recalc() -> Result<(), Box<dyn std::error::Error>> {
+                Ok(())
+            }
+        };
+        insta::assert_snapshot!(pretty(expand_xl_command(quote!(shortcut = "R"), item)));
+    }
+}
@@ -0,0 +1,60 @@
+//! Fixed size array arguments checked for their exact size before the user function sees them
+use xladd_derive::xl_func;
+use xladd_derive_runtime::XlValue;
+
+/// Determinant of a 2x2 matrix
+/// * m - the matrix
+#[xl_func()]
+pub fn det2(m: [[f64; 2]; 2]) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(m[0][0] * m[1][1] - m[0][1] * m[1][0])
+}
+
+/// Length of a point
+/// * p - the point
+#[xl_func()]
+pub fn norm3(p: [f64; 3]) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(p.iter().map(|x| x * x).sum::<f64>().sqrt())
+}
+
+/// Sums the diagonal, with arguments named like the generated indices
+/// * row - a 2x2 matrix
+/// * column - a 2x2 matrix
+#[xl_func()]
+pub fn trace2(row: [[f64; 2]; 2], column: [[f64; 2]; 2]) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(row[0][0] + row[1][1] + column[0][0] + column[1][1])
+}
+
+fn range(rows: &[&[f64]]) -> XlValue {
+    XlValue::Array(rows.iter().map(|row| row.iter().map(|&v| v.into()).collect()).collect())
+}
+
+#[test]
+fn exact_sizes() {
+    assert_eq!(det2_xl(&[range(&[&[1.0, 2.0], &[3.0, 4.0]])]), XlValue::Number(-2.0));
+    assert_eq!(norm3_xl(&[range(&[&[2.0, 3.0, 6.0]])]), XlValue::Number(7.0));
+    assert_eq!(norm3_xl(&[range(&[&[2.0], &[3.0], &[6.0]])]), XlValue::Number(7.0));
+    let identity = range(&[&[1.0, 0.0], &[0.0, 1.0]]);
+    assert_eq!(trace2_xl(&[identity.clone(), identity]), XlValue::Number(4.0));
+}
+
+#[test]
+fn wrong_lengths() {
+    assert_eq!(
+        norm3_xl(&[range(&[&[1.0, 2.0, 3.0, 4.0, 5.0]])]),
+        XlValue::Text("norm3 expected 3 values for argument p, got 5".into())
+    );
+    assert_eq!(norm3_xl(&[1.0.into()]), XlValue::Text("norm3 expected 3 values for argument p, got 1".into()));
+}
+
+#[test]
+fn wrong_shapes() {
+    assert_eq!(
+        det2_xl(&[range(&[&[1.0, 2.0], &[3.0, 4.0], &[5.0, 6.0]])]),
+        XlValue::Text("det2 expected a 2x2 range for argument m, got 3x2".into())
+    );
+    // The right number of values in the wrong shape is still rejected
+    assert_eq!(
+        det2_xl(&[range(&[&[1.0, 2.0, 3.0, 4.0]])]),
+        XlValue::Text("det2 expected a 2x2 range for argument m, got 1x4".into())
+    );
+}
@@ -0,0 +1,91 @@
+//! The generated `xl_<name>` entry points called the way Excel calls them, with the `mock` feature of
+//! the runtime answering the callbacks they make
+use xladd::variant::Variant;
+use xladd::xlcall::{xlerrDiv0, xlerrNA, LPXLOPER12};
+use xladd_derive::xl_func;
+use xladd_derive_runtime::{mock, XlValue};
+
+/// Adds two numbers
+/// * a - first number
+/// * b - second number
+#[xl_func(category = "Maths")]
+fn add(a: f64, b: f64) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(a + b)
+}
+
+/// Sums a range
+/// * values - the numbers to add
+#[xl_func()]
+fn total(values: &[f64]) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(values.iter().sum())
+}
+
+/// Joins text
+/// * text - the text to join
+#[xl_func()]
+fn join(text: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(text.join("-"))
+}
+
+/// Doubles a number on another thread
+/// * x - the number
+#[xl_func(async)]
+fn slow_double(x: f64) -> Result<Variant, Box<dyn std::error::Error>> {
+    Ok(Variant::from(x * 2.0))
+}
+
+fn value(result: LPXLOPER12) -> XlValue {
+    XlValue::from(&unsafe { mock::result(result) })
+}
+
+#[test]
+fn numbers() {
+    let _excel = mock::start();
+    assert_eq!(value(xl_add(mock::oper(1.0), mock::oper(2.0))), XlValue::Number(3.0));
+}
+
+#[test]
+fn missing_arguments() {
+    let _excel = mock::start();
+    match value(xl_add(mock::oper(1.0), mock::missing())) {
+        XlValue::Text(message) => assert!(message.contains('b') && message.contains("add"), "{}", message),
+        other => panic!("Expected a message, got {:?}", other),
+    }
+}
+
+#[test]
+fn errors() {
+    let _excel = mock::start();
+    // Errors in a range are read as NaN, and NaN goes back to Excel as #N/A
+    assert_eq!(value(xl_total(mock::error(xlerrDiv0))), XlValue::Error(xlerrNA));
+}
+
+#[test]
+fn arrays() {
+    let _excel = mock::start();
+    assert_eq!(value(xl_total(mock::array(&[1.0, 2.0, 3.0, 4.0], 2))), XlValue::Number(10.0));
+    assert_eq!(value(xl_join(mock::text_array(&["a", "b", "c"], 3))), XlValue::Text("a-b-c".into()));
+}
+
+#[test]
+fn registrations() {
+    let excel = mock::start();
+    assert!(xladd_derive_runtime::register_all());
+    let registrations = excel.registrations();
+    let add = registrations.iter().find(|r| r.name == "xl_add").expect("xl_add was not registered");
+    assert_eq!(add.type_text, "QQQ");
+    assert_eq!(add.arg_text, "a,b");
+    assert_eq!(add.category, "Maths");
+    assert_eq!(add.help_text, "Adds two numbers");
+    assert_eq!(add.arg_help, vec!["first number".to_string(), "second number".to_string()]);
+    let slow_double = registrations.iter().find(|r| r.name == "xl_slow_double").expect("xl_slow_double was not registered");
+    assert_eq!(slow_double.type_text, ">QX");
+}
+
+#[test]
+fn async_return() {
+    let excel = mock::start();
+    xl_slow_double(mock::oper(2.0), mock::oper(0.0));
+    let returns = excel.wait_for_async(1, std::time::Duration::from_secs(5));
+    assert_eq!(returns.iter().map(XlValue::from).collect::<Vec<_>>(), vec![XlValue::Number(4.0)]);
+}
@@ -0,0 +1,47 @@
+//! `Variant` and `XlRef` arguments handed over as Excel passed them, and `Variant` results handed back untouched
+use xladd::variant::Variant;
+use xladd::xlcall::{xlerrDiv0, LPXLOPER12};
+use xladd_derive::xl_func;
+use xladd_derive_runtime::{mock, XlRef, XlValue};
+
+/// Describes a cell
+/// * v - the cell
+#[xl_func()]
+pub fn describe(v: XlRef<'_>) -> Result<Variant, Box<dyn std::error::Error>> {
+    if v.is_missing() {
+        Ok(Variant::from("missing"))
+    } else if v.is_range() {
+        let (rows, columns) = v.dim();
+        Ok(Variant::from(format!("{}x{} range", rows, columns).as_str()))
+    } else {
+        Ok(v.variant().clone())
+    }
+}
+
+/// Returns its argument
+/// * v - the cell
+#[xl_func()]
+pub fn identity(v: &Variant) -> Result<Variant, Box<dyn std::error::Error>> {
+    Ok(v.clone())
+}
+
+fn value(result: LPXLOPER12) -> XlValue {
+    XlValue::from(&unsafe { mock::result(result) })
+}
+
+#[test]
+fn missing_arguments_reach_the_function() {
+    let _excel = mock::start();
+    assert_eq!(value(xl_describe(mock::missing())), XlValue::Text("missing".into()));
+    assert_eq!(describe_xl(&[]), XlValue::Text("missing".into()));
+    assert_eq!(value(xl_identity(mock::missing())), XlValue::Missing);
+}
+
+#[test]
+fn values_are_not_converted() {
+    let _excel = mock::start();
+    assert_eq!(value(xl_describe(mock::array(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 2))), XlValue::Text("3x2 range".into()));
+    assert_eq!(value(xl_describe(mock::oper("spot"))), XlValue::Text("spot".into()));
+    assert_eq!(value(xl_identity(mock::error(xlerrDiv0))), XlValue::Error(xlerrDiv0));
+    assert_eq!(value(xl_identity(mock::oper(true))), XlValue::Bool(true));
+}
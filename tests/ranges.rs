@@ -0,0 +1,51 @@
+//! `XlRange` arguments registered as `U` and given the reference Excel passed, with the mock answering
+//! `xlSheetNm` and `xlCoerce`
+use xladd::variant::Variant;
+use xladd::xlcall::{xlCoerce, xltypeSRef, LPXLOPER12, XLOPER12, XLREF12};
+use xladd_derive::xl_func;
+use xladd_derive_runtime::{mock, XlRange, XlValue};
+
+/// Says where the first number below zero is
+/// * r - the range to look in
+#[xl_func()]
+pub fn first_negative(r: XlRange) -> Result<String, Box<dyn std::error::Error>> {
+    let (_, columns) = r.dim();
+    let values = r.values().to_f64_vec().unwrap_or_default();
+    match values.iter().position(|&v| v < 0.0) {
+        Some(i) => Ok(format!("input at {} is invalid", r.cell_address(i / columns, i % columns).unwrap_or_else(|| i.to_string()))),
+        None => Ok("ok".to_string()),
+    }
+}
+
+fn value(result: LPXLOPER12) -> XlValue {
+    XlValue::from(&unsafe { mock::result(result) })
+}
+
+#[test]
+fn references() {
+    let excel = mock::start();
+    excel.on_call(|function, _| (function == xlCoerce).then(|| Variant::from(&(&[1.0, 2.0, 3.0, -4.0][..], 2))));
+    let mut sref: XLOPER12 = unsafe { std::mem::zeroed() };
+    sref.xltype = xltypeSRef;
+    sref.val.sref.count = 1;
+    sref.val.sref.ref_ = XLREF12 { rwFirst: 2, rwLast: 3, colFirst: 1, colLast: 2 };
+    assert_eq!(
+        value(xl_first_negative(&mut sref)),
+        XlValue::Text("input at [Book1]Sheet1!C4 is invalid".into())
+    );
+}
+
+#[test]
+fn values_typed_into_the_formula() {
+    let _excel = mock::start();
+    assert_eq!(value(xl_first_negative(mock::array(&[1.0, -2.0], 2))), XlValue::Text("input at 1 is invalid".into()));
+    assert_eq!(first_negative_xl(&[1.0.into()]), XlValue::Text("ok".into()));
+}
+
+#[test]
+fn registered_as_references() {
+    let excel = mock::start();
+    xladd_derive_runtime::register_all();
+    let registration = excel.registrations().into_iter().find(|r| r.name == "xl_first_negative").unwrap();
+    assert_eq!(registration.type_text, "QU");
+}
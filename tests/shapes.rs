@@ -0,0 +1,70 @@
+//! Ranges checked against the `shape` of a slice argument before they are flattened
+use xladd_derive::xl_func;
+use xladd_derive_runtime::XlValue;
+
+/// Sums a column
+/// * v - the numbers
+#[xl_func()]
+pub fn sum_column(#[xl(shape = "column")] v: &[f64]) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(v.iter().sum())
+}
+
+/// Sums a row
+/// * v - the numbers
+#[xl_func()]
+pub fn sum_row(#[xl(shape = "row")] v: &[f64]) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(v.iter().sum())
+}
+
+/// Sums a row or column
+/// * v - the numbers
+#[xl_func()]
+pub fn sum_vector(#[xl(shape = "vector")] v: &[f64]) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(v.iter().sum())
+}
+
+/// Sums anything
+/// * v - the numbers
+#[xl_func()]
+pub fn sum_any(v: &[f64]) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(v.iter().sum())
+}
+
+fn range(rows: usize, columns: usize) -> XlValue {
+    XlValue::Array((0..rows).map(|row| (0..columns).map(|column| ((row * columns + column) as f64).into()).collect()).collect())
+}
+
+#[test]
+fn accepted_shapes() {
+    assert_eq!(sum_column_xl(&[range(3, 1)]), XlValue::Number(3.0));
+    assert_eq!(sum_row_xl(&[range(1, 3)]), XlValue::Number(3.0));
+    assert_eq!(sum_vector_xl(&[range(3, 1)]), XlValue::Number(3.0));
+    assert_eq!(sum_vector_xl(&[range(1, 3)]), XlValue::Number(3.0));
+    // A single cell is a row and a column
+    for (single, expected) in [(2.0.into(), 2.0), (range(1, 1), 0.0)] {
+        assert_eq!(sum_column_xl(std::slice::from_ref(&single)), XlValue::Number(expected));
+        assert_eq!(sum_row_xl(std::slice::from_ref(&single)), XlValue::Number(expected));
+        assert_eq!(sum_vector_xl(&[single]), XlValue::Number(expected));
+    }
+}
+
+#[test]
+fn rejected_shapes() {
+    assert_eq!(
+        sum_column_xl(&[range(3, 2)]),
+        XlValue::Text("sum_column expects a single column for argument v but was given a 3x2 range".into())
+    );
+    assert_eq!(
+        sum_row_xl(&[range(3, 1)]),
+        XlValue::Text("sum_row expects a single row for argument v but was given a 3x1 range".into())
+    );
+    assert_eq!(
+        sum_vector_xl(&[range(2, 2)]),
+        XlValue::Text("sum_vector expects a single row or column for argument v but was given a 2x2 range".into())
+    );
+}
+
+#[test]
+fn unchecked_ranges_are_flattened() {
+    assert_eq!(sum_any_xl(&[range(3, 4)]), XlValue::Number(66.0));
+}
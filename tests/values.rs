@@ -0,0 +1,49 @@
+//! The `<name>_xl` functions and the `xl_<name>` entry points called with the same arguments, showing
+//! they convert and fail the same way
+use xladd::xlcall::{xlerrNA, LPXLOPER12};
+use xladd_derive::xl_func;
+use xladd_derive_runtime::{mock, XlValue};
+
+/// Adds two numbers
+/// * a - first number
+/// * b - second number
+#[xl_func()]
+pub fn add(a: f64, b: f64) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(a + b)
+}
+
+fn value(result: LPXLOPER12) -> XlValue {
+    XlValue::from(&unsafe { mock::result(result) })
+}
+
+#[test]
+fn numbers() {
+    let _excel = mock::start();
+    assert_eq!(add_xl(&[1.0.into(), 2.0.into()]), XlValue::Number(3.0));
+    assert_eq!(value(xl_add(mock::oper(1.0), mock::oper(2.0))), XlValue::Number(3.0));
+}
+
+#[test]
+fn missing_argument() {
+    let _excel = mock::start();
+    let from_values = add_xl(&[1.0.into()]);
+    let message = from_values.as_str().expect("Expected a message");
+    assert!(message.contains('b') && message.contains("add"), "{}", message);
+    assert_eq!(value(xl_add(mock::oper(1.0), mock::missing())), from_values);
+    assert_eq!(add_xl(&[1.0.into(), XlValue::Missing]), from_values);
+}
+
+#[test]
+fn conversion_error() {
+    let _excel = mock::start();
+    let from_values = add_xl(&[XlValue::Text("one".into()), 2.0.into()]);
+    assert!(from_values.as_str().is_some(), "{:?}", from_values);
+    assert_eq!(value(xl_add(mock::oper("one"), mock::oper(2.0))), from_values);
+}
+
+#[test]
+fn nan_is_not_available() {
+    let _excel = mock::start();
+    assert_eq!(add_xl(&[f64::INFINITY.into(), f64::NEG_INFINITY.into()]), XlValue::Error(xlerrNA));
+    assert_eq!(value(xl_add(mock::oper(f64::INFINITY), mock::oper(f64::NEG_INFINITY))), XlValue::Error(xlerrNA));
+}
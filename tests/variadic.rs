@@ -0,0 +1,46 @@
+//! Variadic arguments collected from their slots, including ones named like the generated locals
+use xladd::xlcall::LPXLOPER12;
+use xladd_derive::xl_func;
+use xladd_derive_runtime::{mock, XlValue};
+
+/// Sums its arguments
+/// * values - the numbers to add
+#[xl_func()]
+pub fn total(#[xl(variadic, count = 3)] values: &[f64]) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(values.iter().sum())
+}
+
+/// Joins its arguments
+/// * separator - placed between the values
+/// * v - the text to join
+#[xl_func()]
+pub fn join_all(separator: String, #[xl(variadic, count = 3)] v: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(v.join(&separator))
+}
+
+fn value(result: LPXLOPER12) -> XlValue {
+    XlValue::from(&unsafe { mock::result(result) })
+}
+
+#[test]
+fn slots_are_collected() {
+    let _excel = mock::start();
+    assert_eq!(value(xl_total(mock::oper(1.0), mock::missing(), mock::array(&[2.0, 3.0], 2))), XlValue::Number(6.0));
+    assert_eq!(total_xl(&[1.0.into(), 2.0.into()]), XlValue::Number(3.0));
+    assert_eq!(total_xl(&[]), XlValue::Number(0.0));
+    assert_eq!(
+        value(xl_join_all(mock::oper("-"), mock::oper("a"), mock::text_array(&["b", "c"], 1), mock::missing())),
+        XlValue::Text("a-b-c".into())
+    );
+}
+
+#[test]
+fn slots_are_named_after_the_argument() {
+    let excel = mock::start();
+    xladd_derive_runtime::register_all();
+    let registrations = excel.registrations();
+    let total = registrations.iter().find(|r| r.name == "xl_total").unwrap();
+    assert_eq!(total.arg_text, "values1,values2,values3");
+    let join_all = registrations.iter().find(|r| r.name == "xl_join_all").unwrap();
+    assert_eq!(join_all.arg_text, "separator,v1,v2,v3");
+}